@@ -0,0 +1,786 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512_256};
+
+use crate::http_client::{HttpClient, HttpRequest, HttpResponse};
+use crate::middleware::{BoxFuture, Middleware, Next};
+use crate::url::{Url, percent_encode};
+
+/// Tokens this close to expiry are refreshed before they are sent
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// One challenge from a `WWW-Authenticate` header (RFC 9110 §11.6.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    /// Scheme as sent, e.g. `Digest`
+    pub scheme: String,
+    /// Auth parameters with lowercased names; a token68 is stored under ""
+    pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Split a `WWW-Authenticate` value into challenges
+///
+/// A value may hold several challenges, e.g.
+/// `Digest realm="api", nonce="abc", Basic realm="api"`.
+pub fn parse_challenges(value: &str) -> Vec<Challenge> {
+    let bytes = value.as_bytes();
+    let mut challenges: Vec<Challenge> = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < bytes.len() && b" \t,".contains(&bytes[i]) {
+            i += 1;
+        }
+        if i >= bytes.len() {
+            break;
+        }
+
+        let start = i;
+        // Token characters, plus `/` so token68 values are read whole
+        while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~/".contains(&bytes[i])) {
+            i += 1;
+        }
+        if i == start {
+            i += 1;
+            continue;
+        }
+        let token = &value[start..i];
+
+        let mut next = i;
+        while next < bytes.len() && b" \t".contains(&bytes[next]) {
+            next += 1;
+        }
+        let Some(challenge) = challenges.last_mut().filter(|_| bytes.get(next) == Some(&b'=')) else {
+            challenges.push(Challenge { scheme: token.to_string(), params: Vec::new() });
+            continue;
+        };
+
+        if matches!(bytes.get(next + 1), None | Some(b'=' | b',')) {
+            // token68, e.g. `Negotiate abc==`
+            i = next;
+            while i < bytes.len() && bytes[i] == b'=' {
+                i += 1;
+            }
+            challenge.params.push((String::new(), value[start..i].to_string()));
+            continue;
+        }
+
+        i = next + 1;
+        while i < bytes.len() && b" \t".contains(&bytes[i]) {
+            i += 1;
+        }
+        let param = if bytes.get(i) == Some(&b'"') {
+            let mut unquoted = Vec::new();
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                if bytes[i] == b'\\' && i + 1 < bytes.len() {
+                    i += 1;
+                }
+                unquoted.push(bytes[i]);
+                i += 1;
+            }
+            i += 1;
+            String::from_utf8_lossy(&unquoted).into_owned()
+        } else {
+            let start = i;
+            while i < bytes.len() && !b" \t,".contains(&bytes[i]) {
+                i += 1;
+            }
+            value[start..i].to_string()
+        };
+        challenge.params.push((token.to_ascii_lowercase(), param));
+    }
+
+    challenges
+}
+
+/// Hash functions defined for Digest (RFC 7616 §6.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DigestHash {
+    Md5,
+    Sha256,
+    Sha512_256,
+}
+
+impl DigestHash {
+    fn hex(self, data: &str) -> String {
+        let digest = match self {
+            DigestHash::Md5 => Md5::digest(data).to_vec(),
+            DigestHash::Sha256 => Sha256::digest(data).to_vec(),
+            DigestHash::Sha512_256 => Sha512_256::digest(data).to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// A Digest challenge this client can answer (RFC 7616)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    /// Algorithm as sent, e.g. `SHA-256` or `MD5-sess`
+    pub algorithm: String,
+    /// Offered qop values, lowercased
+    pub qop: Vec<String>,
+    /// The previous nonce expired but the credentials were accepted
+    pub stale: bool,
+    /// The server wants the username hashed
+    pub userhash: bool,
+    hash: DigestHash,
+    session: bool,
+}
+
+impl DigestChallenge {
+    /// The first Digest challenge with a supported algorithm
+    pub fn from_headers(response: &HttpResponse) -> Option<Self> {
+        response.headers.get_all("www-authenticate")
+            .flat_map(parse_challenges)
+            .filter(|challenge| challenge.scheme.eq_ignore_ascii_case("digest"))
+            .find_map(|challenge| Self::from_challenge(&challenge))
+    }
+
+    pub fn from_challenge(challenge: &Challenge) -> Option<Self> {
+        let algorithm = challenge.param("algorithm").unwrap_or("MD5").to_string();
+        let upper = algorithm.to_ascii_uppercase();
+        let (base, session) = match upper.strip_suffix("-SESS") {
+            Some(base) => (base, true),
+            None => (upper.as_str(), false),
+        };
+        let hash = match base {
+            "MD5" => DigestHash::Md5,
+            "SHA-256" => DigestHash::Sha256,
+            "SHA-512-256" => DigestHash::Sha512_256,
+            _ => return None,
+        };
+        let flag = |name| challenge.param(name).is_some_and(|value| value.eq_ignore_ascii_case("true"));
+
+        Some(DigestChallenge {
+            realm: challenge.param("realm").unwrap_or_default().to_string(),
+            nonce: challenge.param("nonce")?.to_string(),
+            opaque: challenge.param("opaque").map(str::to_string),
+            algorithm,
+            qop: challenge.param("qop").unwrap_or_default()
+                .split(',')
+                .map(|qop| qop.trim().to_ascii_lowercase())
+                .filter(|qop| !qop.is_empty())
+                .collect(),
+            stale: flag("stale"),
+            userhash: flag("userhash"),
+            hash,
+            session,
+        })
+    }
+
+    /// The `Authorization` value for one request
+    ///
+    /// `nc` is the number of requests already sent with this nonce plus
+    /// one. `auth` is preferred over `auth-int`.
+    pub fn authorization(&self, username: &str, password: &str, request: &HttpRequest, nc: u32, cnonce: &str) -> String {
+        let h = |data: &str| self.hash.hex(data);
+        let (method, body) = (request.method(), request.body());
+        let uri = Url::parse(request.path()).map_or_else(|_| request.path().to_string(), |url| url.request_target());
+        let uri = uri.as_str();
+        let qop = ["auth", "auth-int"].into_iter().find(|qop| self.qop.iter().any(|offered| offered == qop));
+        let nc = format!("{:08x}", nc);
+
+        let mut ha1 = h(&format!("{}:{}:{}", username, self.realm, password));
+        if self.session {
+            ha1 = h(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = match qop {
+            Some("auth-int") => h(&format!("{}:{}:{}", method, uri, h(body.unwrap_or_default()))),
+            _ => h(&format!("{}:{}", method, uri)),
+        };
+        let response = match qop {
+            Some(qop) => h(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop, ha2)),
+            None => h(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let username = if self.userhash {
+            format!("username=\"{}\"", h(&format!("{}:{}", username, self.realm)))
+        } else if username.bytes().all(|byte| (0x20..0x7f).contains(&byte)) {
+            format!("username={}", quote(username))
+        } else {
+            format!("username*=UTF-8''{}", percent_encode(username, ""))
+        };
+        let mut header = format!("Digest {}, realm={}, uri={}, algorithm={}, nonce={}",
+            username, quote(&self.realm), quote(uri), self.algorithm, quote(&self.nonce));
+        if let Some(qop) = qop {
+            header.push_str(&format!(", nc={}, cnonce={}, qop={}", nc, quote(cnonce), qop));
+        }
+        header.push_str(&format!(", response=\"{}\"", response));
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque={}", quote(opaque)));
+        }
+        if self.userhash {
+            header.push_str(", userhash=true");
+        }
+        header
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// An OAuth2 access token (RFC 6749 §5.1)
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccessToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl AccessToken {
+    /// A token with no known expiry
+    pub fn new(access_token: &str) -> Self {
+        AccessToken {
+            access_token: access_token.to_string(),
+            refresh_token: None,
+            expires_at: None,
+        }
+    }
+
+    /// Parse a token endpoint response received at `now`
+    pub fn from_response(body: &serde_json::Value, now: SystemTime) -> Result<Self> {
+        let access_token = body["access_token"].as_str()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Token response has no access_token"))?;
+        Ok(AccessToken {
+            access_token: access_token.to_string(),
+            refresh_token: body["refresh_token"].as_str().map(str::to_string),
+            expires_at: body["expires_in"].as_u64().map(|seconds| now + Duration::from_secs(seconds)),
+        })
+    }
+
+    /// Whether the token expires within the refresh margin of `now`
+    pub fn expires_soon(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| now + EXPIRY_MARGIN >= expires_at)
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("access_token", &"***")
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "***"))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// An OAuth2 client registered with a token endpoint
+///
+/// The client authenticates with HTTP Basic (RFC 6749 §2.3.1). Token
+/// requests are sent below the middleware stack, so they are never
+/// themselves authenticated by an [`Authenticator`].
+#[derive(Clone)]
+pub struct OAuth2Client {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+}
+
+impl OAuth2Client {
+    pub fn new(token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        OAuth2Client {
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scope: None,
+        }
+    }
+
+    /// Space-separated scopes to request
+    pub fn set_scope(&mut self, scope: &str) -> &mut Self {
+        self.scope = Some(scope.to_string());
+        self
+    }
+
+    /// Fetch a token with the client-credentials grant (RFC 6749 §4.4)
+    pub async fn client_credentials(&self, client: &HttpClient) -> Result<AccessToken> {
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        self.request_token(client, &form).await
+    }
+
+    /// Exchange a refresh token for a new access token (RFC 6749 §6)
+    ///
+    /// The old refresh token is kept if the server does not issue a new one.
+    pub async fn refresh(&self, client: &HttpClient, refresh_token: &str) -> Result<AccessToken> {
+        let mut form = vec![("grant_type", "refresh_token"), ("refresh_token", refresh_token)];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        let mut token = self.request_token(client, &form).await?;
+        token.refresh_token.get_or_insert_with(|| refresh_token.to_string());
+        Ok(token)
+    }
+
+    async fn request_token(&self, client: &HttpClient, form: &[(&str, &str)]) -> Result<AccessToken> {
+        let body = form.iter()
+            .map(|(name, value)| format!("{}={}", name, form_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let credentials = format!("{}:{}", form_encode(&self.client_id), form_encode(&self.client_secret));
+
+        let mut request = HttpRequest::new("POST", &self.token_url);
+        request.set_header("Authorization", &format!("Basic {}", BASE64.encode(credentials)))
+            .set_header("Content-Type", "application/x-www-form-urlencoded")
+            .set_header("Accept", "application/json")
+            .set_body(&body);
+        let response = client.dispatch(&request).await?;
+
+        if !(200..300).contains(&response.status) {
+            // RFC 6749 §5.2 error response, if the server sent one
+            let error = response.json().ok()
+                .and_then(|body| body["error"].as_str().map(str::to_string))
+                .unwrap_or_else(|| response.status_text.clone());
+            let kind = if matches!(response.status, 400 | 401) { ErrorKind::PermissionDenied } else { ErrorKind::Other };
+            return Err(Error::new(kind, format!("Token request failed with {}: {}", response.status, error)));
+        }
+        AccessToken::from_response(&response.json()?, SystemTime::now())
+    }
+}
+
+impl fmt::Debug for OAuth2Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuth2Client")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `application/x-www-form-urlencoded` value encoding
+fn form_encode(value: &str) -> String {
+    percent_encode(&value.replace('%', "%25"), "")
+}
+
+/// How to authenticate to one origin
+#[derive(Clone)]
+pub enum Credentials {
+    /// Sent with every request
+    Basic { username: String, password: String },
+    /// Sent in answer to a Digest challenge, then with every request while the nonce lasts
+    Digest { username: String, password: String },
+    /// A bearer token, refreshed through `refresh` when it expires or is rejected
+    Bearer { token: AccessToken, refresh: Option<OAuth2Client> },
+    /// Bearer tokens fetched with the OAuth2 client-credentials grant
+    ClientCredentials(OAuth2Client),
+}
+
+impl Credentials {
+    pub fn basic(username: &str, password: &str) -> Self {
+        Credentials::Basic { username: username.to_string(), password: password.to_string() }
+    }
+
+    pub fn digest(username: &str, password: &str) -> Self {
+        Credentials::Digest { username: username.to_string(), password: password.to_string() }
+    }
+
+    /// A fixed bearer token that is never refreshed
+    pub fn bearer(token: &str) -> Self {
+        Credentials::Bearer { token: AccessToken::new(token), refresh: None }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Basic { username, .. } => f.debug_struct("Basic").field("username", username).finish_non_exhaustive(),
+            Credentials::Digest { username, .. } => f.debug_struct("Digest").field("username", username).finish_non_exhaustive(),
+            Credentials::Bearer { token, refresh } => f.debug_struct("Bearer").field("token", token).field("refresh", refresh).finish(),
+            Credentials::ClientCredentials(client) => f.debug_tuple("ClientCredentials").field(client).finish(),
+        }
+    }
+}
+
+struct StoredCredentials {
+    credentials: Credentials,
+    /// Current bearer token; the async lock makes concurrent requests share one refresh
+    token: Arc<tokio::sync::Mutex<Option<AccessToken>>>,
+    /// Last Digest challenge and the nonce count used with it
+    digest: Option<(DigestChallenge, u32)>,
+}
+
+/// Credentials by origin, shared by the clones of a store
+///
+/// Each session keeps its own store in `SessionState::credentials`; use
+/// `deep_clone` for a copy that is not shared.
+#[derive(Clone, Default)]
+pub struct CredentialStore {
+    origins: Arc<Mutex<HashMap<String, StoredCredentials>>>,
+}
+
+impl CredentialStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `credentials` for every URL with the scheme, host and port of `origin`
+    pub fn insert(&self, origin: &str, credentials: Credentials) -> Result<()> {
+        let token = match &credentials {
+            Credentials::Bearer { token, .. } => Some(token.clone()),
+            _ => None,
+        };
+        self.origins.lock().unwrap().insert(origin_key(&Url::parse(origin)?), StoredCredentials {
+            credentials,
+            token: Arc::new(tokio::sync::Mutex::new(token)),
+            digest: None,
+        });
+        Ok(())
+    }
+
+    /// Forget the credentials for `origin`
+    pub fn remove(&self, origin: &str) -> bool {
+        Url::parse(origin).is_ok_and(|url| self.origins.lock().unwrap().remove(&origin_key(&url)).is_some())
+    }
+
+    /// Credentials that apply to `url`
+    pub fn get(&self, url: &str) -> Option<Credentials> {
+        let key = origin_key(&Url::parse(url).ok()?);
+        self.origins.lock().unwrap().get(&key).map(|stored| stored.credentials.clone())
+    }
+
+    /// An independent copy: credentials, tokens and Digest nonce counts
+    /// change separately from here on
+    pub fn deep_clone(&self) -> CredentialStore {
+        let origins = self.origins.lock().unwrap().iter()
+            .map(|(key, stored)| {
+                // While a refresh holds the lock, the copy starts from the configured token
+                let token = stored.token.try_lock().map(|token| token.clone()).unwrap_or_else(|_| match &stored.credentials {
+                    Credentials::Bearer { token, .. } => Some(token.clone()),
+                    _ => None,
+                });
+                (key.clone(), StoredCredentials {
+                    credentials: stored.credentials.clone(),
+                    token: Arc::new(tokio::sync::Mutex::new(token)),
+                    digest: stored.digest.clone(),
+                })
+            })
+            .collect();
+        CredentialStore { origins: Arc::new(Mutex::new(origins)) }
+    }
+
+    /// A usable bearer token for `key`, refreshing it if needed
+    ///
+    /// `rejected` is a token the server refused; it is replaced unless
+    /// another request already did so.
+    async fn bearer_token(&self, key: &str, client: &HttpClient, rejected: Option<&str>) -> Result<Option<AccessToken>> {
+        let Some((credentials, slot)) = self.origins.lock().unwrap().get(key)
+            .map(|stored| (stored.credentials.clone(), stored.token.clone()))
+        else {
+            return Ok(None);
+        };
+
+        let mut token = slot.lock().await;
+        let usable = token.as_ref().is_some_and(|token| {
+            !token.expires_soon(SystemTime::now()) && Some(token.access_token.as_str()) != rejected
+        });
+        if usable {
+            return Ok(token.clone());
+        }
+
+        let refreshed = match (&credentials, token.as_ref()) {
+            (Credentials::ClientCredentials(oauth), _) => oauth.client_credentials(client).await?,
+            (Credentials::Bearer { refresh: Some(oauth), .. }, Some(AccessToken { refresh_token: Some(refresh_token), .. })) => {
+                oauth.refresh(client, refresh_token).await?
+            }
+            // Nothing to refresh with; send what we have
+            _ => return Ok(token.clone()),
+        };
+        *token = Some(refreshed);
+        Ok(token.clone())
+    }
+
+    /// Remember a Digest challenge for `key`, resetting the nonce count
+    fn set_digest_challenge(&self, key: &str, challenge: DigestChallenge) {
+        if let Some(stored) = self.origins.lock().unwrap().get_mut(key) {
+            stored.digest = Some((challenge, 0));
+        }
+    }
+
+    /// The next Digest `Authorization` value for `key`, if a challenge is known
+    fn digest_authorization(&self, key: &str, request: &HttpRequest) -> Option<String> {
+        let mut origins = self.origins.lock().unwrap();
+        let stored = origins.get_mut(key)?;
+        let Credentials::Digest { username, password } = &stored.credentials else {
+            return None;
+        };
+        let (challenge, nc) = stored.digest.as_mut()?;
+        *nc += 1;
+        let cnonce = format!("{:016x}", rand::random::<u64>());
+        Some(challenge.authorization(username, password, request, *nc, &cnonce))
+    }
+}
+
+impl fmt::Debug for CredentialStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let origins = self.origins.lock().unwrap();
+        f.debug_map()
+            .entries(origins.iter().map(|(origin, stored)| (origin, &stored.credentials)))
+            .finish()
+    }
+}
+
+fn origin_key(url: &Url) -> String {
+    format!("{}://{}:{}", url.scheme, url.host, url.port)
+}
+
+/// Middleware that authenticates requests with a [`CredentialStore`]
+///
+/// Requests that already carry an `Authorization` header, or whose origin
+/// has no credentials, pass through untouched. A 401 is retried once: with
+/// a refreshed token for Bearer and client credentials, or with an answer
+/// to the new challenge for Digest.
+///
+/// Streamed requests cannot be retried, so they are authorized up front:
+/// Basic and bearer credentials always, Digest once an earlier request has
+/// received a challenge for the origin.
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    store: CredentialStore,
+}
+
+impl Authenticator {
+    pub fn new(store: CredentialStore) -> Self {
+        Authenticator { store }
+    }
+
+    async fn send_bearer(&self, key: &str, request: HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let Some(token) = self.store.bearer_token(key, next.client(), None).await? else {
+            return next.run(request).await;
+        };
+        let response = next.run(with_authorization(&request, &format!("Bearer {}", token.access_token))).await?;
+        if response.status != 401 {
+            return Ok(response);
+        }
+
+        match self.store.bearer_token(key, next.client(), Some(&token.access_token)).await? {
+            Some(refreshed) if refreshed.access_token != token.access_token => {
+                next.run(with_authorization(&request, &format!("Bearer {}", refreshed.access_token))).await
+            }
+            _ => Ok(response),
+        }
+    }
+
+    async fn send_digest(&self, key: &str, request: HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let response = match self.store.digest_authorization(key, &request) {
+            Some(authorization) => next.run(with_authorization(&request, &authorization)).await?,
+            None => next.run(request.clone()).await?,
+        };
+        if response.status != 401 {
+            return Ok(response);
+        }
+
+        let Some(challenge) = DigestChallenge::from_headers(&response) else {
+            return Ok(response);
+        };
+        self.store.set_digest_challenge(key, challenge);
+        match self.store.digest_authorization(key, &request) {
+            Some(authorization) => next.run(with_authorization(&request, &authorization)).await,
+            None => Ok(response),
+        }
+    }
+}
+
+impl Middleware for Authenticator {
+    fn on_stream_request<'a>(&'a self, request: HttpRequest, client: &'a HttpClient) -> BoxFuture<'a, Result<HttpRequest>> {
+        Box::pin(async move {
+            let Ok(url) = Url::parse(request.path()) else {
+                return Ok(request);
+            };
+            if request.headers().contains("authorization") {
+                return Ok(request);
+            }
+            let key = origin_key(&url);
+
+            let authorization = match self.store.get(request.path()) {
+                Some(Credentials::Basic { username, password }) => {
+                    Some(format!("Basic {}", BASE64.encode(format!("{}:{}", username, password))))
+                }
+                Some(Credentials::Digest { .. }) => self.store.digest_authorization(&key, &request),
+                Some(Credentials::Bearer { .. } | Credentials::ClientCredentials(_)) => {
+                    self.store.bearer_token(&key, client, None).await?.map(|token| format!("Bearer {}", token.access_token))
+                }
+                None => None,
+            };
+            Ok(match authorization {
+                Some(authorization) => with_authorization(&request, &authorization),
+                None => request,
+            })
+        })
+    }
+
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let Ok(url) = Url::parse(request.path()) else {
+                return next.run(request).await;
+            };
+            if request.headers().contains("authorization") {
+                return next.run(request).await;
+            }
+            let key = origin_key(&url);
+
+            match self.store.get(request.path()) {
+                Some(Credentials::Basic { username, password }) => {
+                    let token = BASE64.encode(format!("{}:{}", username, password));
+                    next.run(with_authorization(&request, &format!("Basic {}", token))).await
+                }
+                Some(Credentials::Digest { .. }) => self.send_digest(&key, request, next).await,
+                Some(Credentials::Bearer { .. } | Credentials::ClientCredentials(_)) => self.send_bearer(&key, request, next).await,
+                None => next.run(request).await,
+            }
+        })
+    }
+}
+
+fn with_authorization(request: &HttpRequest, value: &str) -> HttpRequest {
+    let mut request = request.clone();
+    request.set_header("Authorization", value);
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{pooled_client, spawn_server_per_request};
+
+    fn token_response(json: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", json.len(), json)
+    }
+
+    fn authenticated_client(store: &CredentialStore) -> HttpClient {
+        let mut client = pooled_client();
+        client.add_middleware(Authenticator::new(store.clone()));
+        client
+    }
+
+    #[test]
+    fn test_digest_rfc7616_example() {
+        let header = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=SHA-256, \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", \
+            Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=MD5, \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", \
+            Negotiate abc/def==";
+        let challenges = parse_challenges(header);
+        assert_eq!(challenges.len(), 3);
+        assert_eq!(challenges[2].param(""), Some("abc/def=="));
+
+        let request = HttpRequest::new("GET", "http://www.example.org/dir/index.html");
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let sha256 = DigestChallenge::from_challenge(&challenges[0]).unwrap();
+        assert_eq!(sha256.qop, ["auth", "auth-int"]);
+        let authorization = sha256.authorization("Mufasa", "Circle of Life", &request, 1, cnonce);
+        assert!(authorization.starts_with("Digest username=\"Mufasa\", realm=\"http-auth@example.org\", uri=\"/dir/index.html\", algorithm=SHA-256"));
+        assert!(authorization.contains("nc=00000001, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth"));
+        assert!(authorization.contains("response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""));
+
+        let md5 = DigestChallenge::from_challenge(&challenges[1]).unwrap();
+        let authorization = md5.authorization("Mufasa", "Circle of Life", &request, 1, cnonce);
+        assert!(authorization.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+        assert!(authorization.ends_with("opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""));
+    }
+
+    #[tokio::test]
+    async fn test_digest_answers_challenge_then_reuses_nonce() {
+        let (port, server) = spawn_server_per_request(vec![
+            "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"api\", Digest realm=\"api\", nonce=\"n1\", qop=\"auth\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string(),
+        ]);
+        let store = CredentialStore::new();
+        store.insert(&format!("http://127.0.0.1:{}", port), Credentials::digest("user", "pass")).unwrap();
+        let client = authenticated_client(&store);
+
+        for _ in 0..2 {
+            let response = client.execute(&HttpRequest::new("GET", &format!("http://127.0.0.1:{}/private?q=1", port))).await.unwrap();
+            assert_eq!(response.status, 200);
+        }
+
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("Authorization"));
+        assert!(requests[1].contains("Authorization: Digest username=\"user\", realm=\"api\", uri=\"/private?q=1\", algorithm=MD5, nonce=\"n1\", nc=00000001"));
+        assert!(requests[2].contains("nonce=\"n1\", nc=00000002"));
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_fetch_and_refetch_on_401() {
+        let (token_port, token_server) = spawn_server_per_request(vec![
+            token_response(r#"{"access_token":"first","token_type":"Bearer"}"#),
+            token_response(r#"{"access_token":"second","token_type":"Bearer","expires_in":3600}"#),
+        ]);
+        let (api_port, api_server) = spawn_server_per_request(vec![
+            "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Bearer error=\"invalid_token\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string(),
+        ]);
+
+        let mut oauth = OAuth2Client::new(&format!("http://127.0.0.1:{}/token", token_port), "agent", "s3cret&");
+        oauth.set_scope("read write");
+        let store = CredentialStore::new();
+        store.insert(&format!("http://127.0.0.1:{}", api_port), Credentials::ClientCredentials(oauth)).unwrap();
+        let client = authenticated_client(&store);
+
+        let response = client.execute(&HttpRequest::new("GET", &format!("http://127.0.0.1:{}/data", api_port))).await.unwrap();
+        assert_eq!(response.text().unwrap(), "ok");
+
+        let token_requests = token_server.join().unwrap();
+        assert!(token_requests[0].starts_with("POST /token HTTP/1.1\r\n"));
+        assert!(token_requests[0].contains(&format!("Authorization: Basic {}\r\n", BASE64.encode("agent:s3cret%26"))));
+        assert!(token_requests[0].ends_with("\r\n\r\ngrant_type=client_credentials&scope=read%20write"));
+        let api_requests = api_server.join().unwrap();
+        assert!(api_requests[0].contains("Authorization: Bearer first\r\n"));
+        assert!(api_requests[1].contains("Authorization: Bearer second\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_download_from_authenticated_origin() {
+        let (token_port, token_server) = spawn_server_per_request(vec![
+            token_response(r#"{"access_token":"t1","token_type":"Bearer","expires_in":3600}"#),
+        ]);
+        let (api_port, api_server) = spawn_server_per_request(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndata".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nmore".to_string(),
+        ]);
+
+        let oauth = OAuth2Client::new(&format!("http://127.0.0.1:{}/token", token_port), "agent", "secret");
+        let store = CredentialStore::new();
+        store.insert(&format!("http://127.0.0.1:{}", api_port), Credentials::ClientCredentials(oauth)).unwrap();
+        let client = authenticated_client(&store);
+        let path = std::env::temp_dir().join(format!("biosurf-auth-download-{}", api_port));
+
+        let request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/file.bin", api_port));
+        client.download(&request, &path, &crate::download::DownloadOptions::new()).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+        std::fs::remove_file(&path).unwrap();
+
+        // Streaming goes through the same hook, reusing the fetched token
+        let mut response = client.execute_stream(&request).await.unwrap();
+        assert_eq!(response.body.next_chunk().await.unwrap().unwrap(), b"more");
+        drop(response);
+
+        assert_eq!(token_server.join().unwrap().len(), 1);
+        let api_requests = api_server.join().unwrap();
+        assert!(api_requests.iter().all(|request| request.contains("Authorization: Bearer t1\r\n")));
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::io::{BufRead, Error, Result};
+
+use tokio::io::AsyncBufRead;
+
+use crate::error::{HttpError, find_cause};
+use crate::http1::{self, BodyFraming, ResponseDecoder, ResponseHead};
+
+/// A response body grew past the configured maximum
+///
+/// Carried inside `std::io::Error` (kind `FileTooLarge`) as
+/// [`HttpError::BodyLimit`]; recover it with [`BodyTooLarge::find`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyTooLarge {
+    pub limit: u64,
+}
+
+impl BodyTooLarge {
+    pub fn error(limit: u64) -> Error {
+        HttpError::BodyLimit(BodyTooLarge { limit }).into()
+    }
+
+    /// The `BodyTooLarge` inside an I/O error, if that is what it wraps
+    pub fn find(error: &Error) -> Option<&BodyTooLarge> {
+        find_cause(error)
+    }
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Response body exceeds the maximum size of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// Running body size checked against an optional maximum
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimit {
+    max: Option<u64>,
+    received: u64,
+}
+
+impl BodyLimit {
+    pub fn new(max: Option<u64>) -> Self {
+        BodyLimit { max, received: 0 }
+    }
+
+    /// Count `len` more bytes, failing once the total passes the maximum
+    pub fn add(&mut self, len: usize) -> Result<()> {
+        self.received += len as u64;
+        match self.max {
+            Some(max) if self.received > max => Err(BodyTooLarge::error(max)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Fail before reading a body whose Content-Length is already over the maximum
+    pub fn check_declared(&self, head: &ResponseHead, method: &str) -> Result<()> {
+        match (self.max, head.body_framing(method)) {
+            (Some(max), Ok(BodyFraming::ContentLength(length))) if length > max => Err(BodyTooLarge::error(max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Body of an HTTP/1.x response, read chunk by chunk from a buffered stream
+///
+/// Chunks are transfer-decoded (chunked framing removed) but not
+/// content-decoded. After the last chunk the stream is positioned at the
+/// next response, so a keep-alive connection can be reused. The first error
+/// ends the stream.
+pub struct BodyStream<'a, R> {
+    reader: &'a mut R,
+    decoder: ResponseDecoder,
+    limit: BodyLimit,
+    failed: bool,
+}
+
+impl<'a, R: BufRead> BodyStream<'a, R> {
+    pub fn new(reader: &'a mut R, decoder: ResponseDecoder, max_body_size: Option<u64>) -> Self {
+        BodyStream {
+            reader,
+            decoder,
+            limit: BodyLimit::new(max_body_size),
+            failed: false,
+        }
+    }
+
+    /// Whether the whole body has been read
+    pub fn is_complete(&self) -> bool {
+        self.decoder.is_complete()
+    }
+
+    /// Bytes of body read so far
+    pub fn received(&self) -> u64 {
+        self.limit.received()
+    }
+}
+
+impl<R: BufRead> Iterator for BodyStream<'_, R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = http1::read_chunk(self.reader, &mut self.decoder)
+            .and_then(|chunk| chunk.map(|chunk| self.limit.add(chunk.len()).map(|()| chunk)).transpose());
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+/// Async counterpart of [`BodyStream`]; call [`AsyncBodyStream::next_chunk`] until it returns `None`
+pub struct AsyncBodyStream<'a, R> {
+    reader: &'a mut R,
+    decoder: ResponseDecoder,
+    limit: BodyLimit,
+    failed: bool,
+}
+
+impl<'a, R: AsyncBufRead + Unpin> AsyncBodyStream<'a, R> {
+    pub fn new(reader: &'a mut R, decoder: ResponseDecoder, max_body_size: Option<u64>) -> Self {
+        AsyncBodyStream {
+            reader,
+            decoder,
+            limit: BodyLimit::new(max_body_size),
+            failed: false,
+        }
+    }
+
+    /// Next run of body bytes, or `None` once the body is complete
+    pub async fn next_chunk(&mut self) -> Option<Result<Vec<u8>>> {
+        if self.failed {
+            return None;
+        }
+        let result = http1::read_chunk_async(self.reader, &mut self.decoder).await
+            .and_then(|chunk| chunk.map(|chunk| self.limit.add(chunk.len()).map(|()| chunk)).transpose());
+        self.failed = result.is_err();
+        result.transpose()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.decoder.is_complete()
+    }
+
+    pub fn received(&self) -> u64 {
+        self.limit.received()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_stream_chunked_body() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\nNEXT";
+        let mut cursor = Cursor::new(&input[..]);
+        let mut decoder = ResponseDecoder::new("GET");
+        let head = http1::read_head(&mut cursor, &mut decoder).unwrap();
+        assert_eq!(head.status, 200);
+
+        let mut body = BodyStream::new(&mut cursor, decoder, None);
+        let chunks: Vec<Vec<u8>> = body.by_ref().collect::<Result<_>>().unwrap();
+        assert_eq!(chunks, [b"abc".to_vec(), b"de".to_vec()]);
+        assert!(body.is_complete());
+        assert_eq!(&input[cursor.position() as usize..], b"NEXT");
+    }
+
+    #[test]
+    fn test_stream_stops_at_limit() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n4\r\nefgh\r\n4\r\nijkl\r\n0\r\n\r\n";
+        let mut cursor = Cursor::new(&input[..]);
+        let mut decoder = ResponseDecoder::new("GET");
+        http1::read_head(&mut cursor, &mut decoder).unwrap();
+
+        let mut body = BodyStream::new(&mut cursor, decoder, Some(6));
+        assert_eq!(body.next().unwrap().unwrap(), b"abcd");
+        let error = body.next().unwrap().unwrap_err();
+        assert_eq!(BodyTooLarge::find(&error), Some(&BodyTooLarge { limit: 6 }));
+
+        // Nothing more is read after the error
+        assert!(body.next().is_none());
+        assert!(input[cursor.position() as usize..].starts_with(b"\r\n4\r\nijkl"));
+    }
+
+    #[test]
+    fn test_declared_length_over_limit() {
+        let head = ResponseHead::parse(b"HTTP/1.1 200 OK\r\nContent-Length: 100").unwrap();
+        let error = BodyLimit::new(Some(6)).check_declared(&head, "GET").unwrap_err();
+        assert_eq!(BodyTooLarge::find(&error), Some(&BodyTooLarge { limit: 6 }));
+        assert!(BodyLimit::new(Some(6)).check_declared(&head, "HEAD").is_ok());
+        assert!(BodyLimit::new(None).check_declared(&head, "GET").is_ok());
+    }
+}
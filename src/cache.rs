@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::headers::HeaderMap;
+use crate::http_client::HttpResponse;
+use crate::machine_http::MachineHttpMeta;
+use crate::retry::parse_http_date;
+
+/// Statuses that may be cached without explicit freshness (RFC 9110 §15.1)
+const HEURISTIC_STATUSES: [u16; 10] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 501];
+
+/// Upper bound for freshness guessed from Last-Modified
+const MAX_HEURISTIC_FRESHNESS: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Connection-specific fields that are never stored (RFC 9111 §3.1)
+const UNSTORED_HEADERS: [&str; 3] = ["connection", "keep-alive", "transfer-encoding"];
+
+/// Fields a 304 must not overwrite in the stored response (RFC 9111 §3.2)
+const KEPT_ON_UPDATE: [&str; 5] = ["content-length", "content-encoding", "transfer-encoding", "connection", "keep-alive"];
+
+/// How the cache took part in producing a response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// Fetched from the origin (or no cache is configured)
+    #[default]
+    Miss,
+    /// Served from the cache without contacting the origin
+    Hit,
+    /// Served from the cache after the origin answered 304 Not Modified
+    Revalidated,
+    /// Served from the cache past its freshness lifetime while a background
+    /// request revalidates it (stale-while-revalidate)
+    Stale,
+}
+
+/// Cache-Control directives of a request or response (RFC 9111 §5.2)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub must_revalidate: bool,
+    pub only_if_cached: bool,
+    pub max_age: Option<Duration>,
+    /// Acceptable staleness; `Duration::MAX` when given without a value
+    pub max_stale: Option<Duration>,
+    pub min_fresh: Option<Duration>,
+}
+
+impl CacheControl {
+    /// Parse every Cache-Control field value of a message
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut directives = CacheControl::default();
+
+        for directive in values.into_iter().flat_map(|value| value.split(',')) {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim(), Some(argument.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = argument.and_then(|argument| argument.parse::<u64>().ok()).map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                "no-cache" => directives.no_cache = true,
+                "must-revalidate" => directives.must_revalidate = true,
+                "only-if-cached" => directives.only_if_cached = true,
+                // An invalid max-age makes the response stale
+                "max-age" => directives.max_age = Some(seconds.unwrap_or(Duration::ZERO)),
+                "max-stale" => directives.max_stale = Some(seconds.unwrap_or(Duration::MAX)),
+                "min-fresh" => directives.min_fresh = seconds,
+                _ => {}
+            }
+        }
+
+        directives
+    }
+
+    fn of(headers: &HeaderMap) -> Self {
+        Self::parse(headers.get_all("cache-control"))
+    }
+}
+
+/// A stored response and the request header values it was selected by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub version: String,
+    pub status: u16,
+    pub status_text: String,
+    pub headers: HeaderMap,
+    /// Decoded body, as in `HttpResponse::body`
+    pub body: Vec<u8>,
+    /// Lowercased names from Vary and the request's values for them
+    pub vary: Vec<(String, Option<String>)>,
+    pub request_time: SystemTime,
+    pub response_time: SystemTime,
+}
+
+impl CacheEntry {
+    /// Whether this variant was stored for a request with these headers
+    pub fn matches(&self, request_headers: &HeaderMap) -> bool {
+        self.vary.iter().all(|(name, value)| header_value(request_headers, name) == *value)
+    }
+
+    /// How long the response stays fresh (RFC 9111 §4.2.1)
+    pub fn freshness_lifetime(&self) -> Duration {
+        if let Some(max_age) = CacheControl::of(&self.headers).max_age {
+            return max_age;
+        }
+        let date = self.date();
+        if let Some(expires) = self.headers.get("expires") {
+            // An invalid Expires means already expired
+            return parse_http_date(expires)
+                .and_then(|expires| expires.duration_since(date).ok())
+                .unwrap_or(Duration::ZERO);
+        }
+        if HEURISTIC_STATUSES.contains(&self.status)
+            && let Some(last_modified) = self.headers.get("last-modified").and_then(parse_http_date)
+            && let Ok(unchanged) = date.duration_since(last_modified)
+        {
+            return (unchanged / 10).min(MAX_HEURISTIC_FRESHNESS);
+        }
+        Duration::ZERO
+    }
+
+    /// Age of the response at `now` (RFC 9111 §4.2.3)
+    pub fn current_age(&self, now: SystemTime) -> Duration {
+        let apparent_age = self.response_time.duration_since(self.date()).unwrap_or(Duration::ZERO);
+        let response_delay = self.response_time.duration_since(self.request_time).unwrap_or(Duration::ZERO);
+        let age_value = self.headers.get("age")
+            .and_then(|age| age.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::ZERO);
+        let resident_time = now.duration_since(self.response_time).unwrap_or(Duration::ZERO);
+        apparent_age.max(age_value + response_delay) + resident_time
+    }
+
+    /// Headers that make a request conditional on this response being unchanged
+    pub fn validators(&self) -> Vec<(&'static str, &str)> {
+        let mut validators = Vec::new();
+        if let Some(etag) = self.headers.get("etag") {
+            validators.push(("If-None-Match", etag));
+        }
+        if let Some(last_modified) = self.headers.get("last-modified") {
+            validators.push(("If-Modified-Since", last_modified));
+        }
+        validators
+    }
+
+    /// The stored response as served at `now`, with an Age header
+    pub fn to_response(&self, now: SystemTime, cache_status: CacheStatus) -> HttpResponse {
+        let mut headers = self.headers.clone();
+        headers.insert("Age", &self.current_age(now).as_secs().to_string());
+        HttpResponse {
+            version: self.version.clone(),
+            status: self.status,
+            status_text: self.status_text.clone(),
+            headers,
+            body: self.body.clone(),
+            encoded_size: self.body.len(),
+            redirects: Vec::new(),
+            timings: Default::default(),
+            peer_leaf_certificate: None,
+            cache_status,
+            machine: MachineHttpMeta::from_headers(&self.headers),
+        }
+    }
+
+    fn date(&self) -> SystemTime {
+        self.headers.get("date").and_then(parse_http_date).unwrap_or(self.response_time)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "version": self.version,
+            "status": self.status,
+            "status_text": self.status_text,
+            "headers": self.headers.iter().collect::<Vec<_>>(),
+            "body": STANDARD.encode(&self.body),
+            "vary": self.vary,
+            "request_time": unix_millis(self.request_time),
+            "response_time": unix_millis(self.response_time),
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let mut headers = HeaderMap::new();
+        for pair in value["headers"].as_array()? {
+            headers.append(pair[0].as_str()?, pair[1].as_str()?);
+        }
+        let vary = value["vary"].as_array()?.iter()
+            .map(|pair| Some((pair[0].as_str()?.to_string(), pair[1].as_str().map(str::to_string))))
+            .collect::<Option<_>>()?;
+
+        Some(CacheEntry {
+            version: value["version"].as_str()?.to_string(),
+            status: u16::try_from(value["status"].as_u64()?).ok()?,
+            status_text: value["status_text"].as_str()?.to_string(),
+            headers,
+            body: STANDARD.decode(value["body"].as_str()?).ok()?,
+            vary,
+            request_time: UNIX_EPOCH + Duration::from_millis(value["request_time"].as_u64()?),
+            response_time: UNIX_EPOCH + Duration::from_millis(value["response_time"].as_u64()?),
+        })
+    }
+}
+
+/// Where an [`HttpCache`] keeps its entries
+///
+/// Each key (an absolute URL) holds the stored variants selected by Vary,
+/// most recent first.
+pub trait CacheStorage: Send + Sync {
+    /// Stored variants for `key`, empty if there are none
+    fn load(&self, key: &str) -> Result<Vec<CacheEntry>>;
+
+    /// Replace the variants for `key`; an empty slice removes the key
+    fn save(&self, key: &str, entries: &[CacheEntry]) -> Result<()>;
+}
+
+/// Cache storage that lives as long as the process
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<HashMap<String, Vec<CacheEntry>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn load(&self, key: &str) -> Result<Vec<CacheEntry>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned().unwrap_or_default())
+    }
+
+    fn save(&self, key: &str, entries: &[CacheEntry]) -> Result<()> {
+        let mut stored = self.entries.lock().unwrap();
+        if entries.is_empty() {
+            stored.remove(key);
+        } else {
+            stored.insert(key.to_string(), entries.to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// Cache storage with one JSON file per URL in a directory
+#[derive(Debug, Clone)]
+pub struct DiskStorage {
+    directory: PathBuf,
+}
+
+impl DiskStorage {
+    /// Store entries under `directory`, creating it if needed
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(DiskStorage { directory })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let digest = Sha256::digest(key.as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.directory.join(name + ".json")
+    }
+}
+
+impl CacheStorage for DiskStorage {
+    fn load(&self, key: &str) -> Result<Vec<CacheEntry>> {
+        let data = match fs::read(self.path(key)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let value: Value = serde_json::from_slice(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        value.as_array()
+            .and_then(|entries| entries.iter().map(CacheEntry::from_json).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Corrupt cache entry for {}", key)))
+    }
+
+    fn save(&self, key: &str, entries: &[CacheEntry]) -> Result<()> {
+        let path = self.path(key);
+        if entries.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        // Write then rename, so readers never see a half-written file
+        let temporary = path.with_extension("tmp");
+        let entries: Vec<Value> = entries.iter().map(CacheEntry::to_json).collect();
+        fs::write(&temporary, Value::Array(entries).to_string())?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+/// Result of looking a request up in the cache
+#[derive(Debug)]
+pub enum Lookup {
+    /// A fresh response that can be served as is
+    Fresh(HttpResponse),
+    /// A stored response that must be revalidated before use
+    Stale(CacheEntry),
+    Miss,
+}
+
+/// Private HTTP cache (RFC 9111) used by `HttpClient::execute`
+///
+/// Only GET responses are stored. Storage failures are treated as misses,
+/// so a broken cache never fails a request.
+#[derive(Clone)]
+pub struct HttpCache {
+    storage: Arc<dyn CacheStorage>,
+}
+
+impl HttpCache {
+    pub fn new(storage: impl CacheStorage + 'static) -> Self {
+        HttpCache { storage: Arc::new(storage) }
+    }
+
+    pub fn in_memory() -> Self {
+        Self::new(MemoryStorage::new())
+    }
+
+    pub fn on_disk(directory: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self::new(DiskStorage::new(directory)?))
+    }
+
+    /// Find the variant of `url` matching the request headers about to be sent
+    pub fn lookup(&self, url: &str, request_headers: &HeaderMap, now: SystemTime) -> Lookup {
+        let entry = self.storage.load(url).ok()
+            .and_then(|entries| entries.into_iter().find(|entry| entry.matches(request_headers)));
+        let Some(entry) = entry else {
+            return Lookup::Miss;
+        };
+
+        let request = CacheControl::of(request_headers);
+        let response = CacheControl::of(&entry.headers);
+        if request.no_cache || response.no_cache {
+            return Lookup::Stale(entry);
+        }
+
+        let age = entry.current_age(now);
+        let lifetime = entry.freshness_lifetime();
+        let within_max_age = request.max_age.is_none_or(|max_age| age <= max_age);
+        let fresh = age.saturating_add(request.min_fresh.unwrap_or(Duration::ZERO)) < lifetime;
+        let stale_allowed = !response.must_revalidate
+            && request.max_stale.is_some_and(|max_stale| age <= lifetime.saturating_add(max_stale));
+
+        if within_max_age && (fresh || stale_allowed) {
+            Lookup::Fresh(entry.to_response(now, CacheStatus::Hit))
+        } else {
+            Lookup::Stale(entry)
+        }
+    }
+
+    /// Store a response to a GET request if RFC 9111 §3 allows it
+    pub fn store(&self, url: &str, request_headers: &HeaderMap, response: &HttpResponse, request_time: SystemTime, response_time: SystemTime) {
+        if !is_storable(request_headers, response) {
+            return;
+        }
+        let vary = response.headers.get_all("vary")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = header_value(request_headers, &name);
+                (name, value)
+            })
+            .collect();
+
+        let mut headers = response.headers.clone();
+        headers.retain(|name, _| !UNSTORED_HEADERS.iter().any(|unstored| name.eq_ignore_ascii_case(unstored)));
+        self.replace(url, request_headers, CacheEntry {
+            version: response.version.clone(),
+            status: response.status,
+            status_text: response.status_text.clone(),
+            headers,
+            body: response.body.clone(),
+            vary,
+            request_time,
+            response_time,
+        });
+    }
+
+    /// Update a stale entry from a 304 and return the stored response (RFC 9111 §4.3.4)
+    pub fn freshen(
+        &self,
+        url: &str,
+        request_headers: &HeaderMap,
+        mut entry: CacheEntry,
+        not_modified: &HttpResponse,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) -> HttpResponse {
+        let updated = |name: &str| {
+            !KEPT_ON_UPDATE.iter().any(|kept| name.eq_ignore_ascii_case(kept))
+                && !UNSTORED_HEADERS.iter().any(|unstored| name.eq_ignore_ascii_case(unstored))
+        };
+        entry.headers.retain(|name, _| !(updated(name) && not_modified.headers.contains(name)));
+        for (name, value) in not_modified.headers.iter().filter(|(name, _)| updated(name)) {
+            entry.headers.append(name, value);
+        }
+        entry.request_time = request_time;
+        entry.response_time = response_time;
+
+        let response = entry.to_response(response_time, CacheStatus::Revalidated);
+        self.replace(url, request_headers, entry);
+        response
+    }
+
+    /// Drop everything stored for `url`, e.g. after a successful unsafe request
+    pub fn invalidate(&self, url: &str) {
+        let _ = self.storage.save(url, &[]);
+    }
+
+    fn replace(&self, url: &str, request_headers: &HeaderMap, entry: CacheEntry) {
+        let mut entries = self.storage.load(url).unwrap_or_default();
+        entries.retain(|stored| !stored.matches(request_headers));
+        entries.insert(0, entry);
+        let _ = self.storage.save(url, &entries);
+    }
+}
+
+/// Whether a response to a GET may be stored (RFC 9111 §3)
+fn is_storable(request_headers: &HeaderMap, response: &HttpResponse) -> bool {
+    let request = CacheControl::of(request_headers);
+    let directives = CacheControl::of(&response.headers);
+    let varies_on_everything = response.headers.get_all("vary")
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim() == "*");
+    let explicit_freshness = directives.max_age.is_some() || response.headers.contains("expires");
+
+    !request.no_store
+        && !directives.no_store
+        && !varies_on_everything
+        && response.status >= 200
+        && response.status != 206
+        && response.status != 304
+        && (explicit_freshness || HEURISTIC_STATUSES.contains(&response.status))
+}
+
+/// Methods that only retrieve and never invalidate stored responses (RFC 9110 §9.2.1)
+pub fn is_safe(method: &str) -> bool {
+    matches!(method.to_ascii_uppercase().as_str(), "GET" | "HEAD" | "OPTIONS" | "TRACE")
+}
+
+/// All values of a request header joined as one list
+pub(crate) fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers.get_all(name).map(str::trim).collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(raw: &str) -> HttpResponse {
+        HttpResponse::parse(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_freshness_and_vary() {
+        let cache = HttpCache::in_memory();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut english = HeaderMap::new();
+        english.append("Accept-Language", "en");
+        let mut german = HeaderMap::new();
+        german.append("Accept-Language", "de");
+
+        let stored = response("HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept-Language\r\nETag: \"a\"\r\nContent-Length: 2\r\n\r\nhi");
+        cache.store("http://example.com/", &english, &stored, now, now);
+
+        let Lookup::Fresh(hit) = cache.lookup("http://example.com/", &english, now + Duration::from_secs(30)) else {
+            panic!("expected a fresh hit");
+        };
+        assert_eq!((hit.body.as_slice(), hit.header("age"), hit.cache_status), (&b"hi"[..], Some("30"), CacheStatus::Hit));
+        assert!(matches!(cache.lookup("http://example.com/", &german, now), Lookup::Miss));
+
+        // Past max-age the entry must be revalidated, unless the request accepts stale responses
+        let later = now + Duration::from_secs(90);
+        let Lookup::Stale(entry) = cache.lookup("http://example.com/", &english, later) else {
+            panic!("expected a stale entry");
+        };
+        assert_eq!(entry.validators(), [("If-None-Match", "\"a\"")]);
+        english.append("Cache-Control", "max-stale=60");
+        assert!(matches!(cache.lookup("http://example.com/", &english, later), Lookup::Fresh(_)));
+
+        let uncacheable = response("HTTP/1.1 200 OK\r\nCache-Control: no-store\r\nContent-Length: 0\r\n\r\n");
+        cache.store("http://example.com/private", &german, &uncacheable, now, now);
+        assert!(matches!(cache.lookup("http://example.com/private", &german, now), Lookup::Miss));
+    }
+
+    #[test]
+    fn test_heuristic_freshness_and_disk_round_trip() {
+        let directory = std::env::temp_dir().join(format!("biosurf-cache-{}", std::process::id()));
+        let cache = HttpCache::on_disk(&directory).unwrap();
+        let date = parse_http_date("Mon, 10 Jan 2022 00:00:00 GMT").unwrap();
+
+        // Ten days since the last change gives a day of heuristic freshness
+        let stored = response("HTTP/1.1 200 OK\r\nDate: Mon, 10 Jan 2022 00:00:00 GMT\r\nLast-Modified: Fri, 31 Dec 2021 00:00:00 GMT\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n\x00\x01\x02\r\n0\r\n\r\n");
+        cache.store("https://example.com/data", &HeaderMap::new(), &stored, date, date);
+
+        let entries = DiskStorage::new(&directory).unwrap().load("https://example.com/data").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].freshness_lifetime(), Duration::from_secs(24 * 60 * 60));
+        assert_eq!(entries[0].body, [0, 1, 2]);
+        assert!(!entries[0].headers.contains("transfer-encoding"));
+
+        cache.invalidate("https://example.com/data");
+        assert!(matches!(cache.lookup("https://example.com/data", &HeaderMap::new(), date), Lookup::Miss));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
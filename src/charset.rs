@@ -0,0 +1,187 @@
+pub use encoding_rs::Encoding;
+
+use encoding_rs::{UTF_8, WINDOWS_1252};
+
+/// How far into an HTML body `<meta>` declarations are looked for
+const PRESCAN_LIMIT: usize = 1024;
+
+/// The `charset` parameter of a Content-Type value, e.g. `text/html; charset="Shift_JIS"`
+pub fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches(|c| c == '"' || c == '\''))
+        .filter(|value| !value.is_empty())
+}
+
+/// Encoding named by a Content-Type value, if it names a known one
+pub fn from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    charset_param(content_type).and_then(|label| Encoding::for_label(label.as_bytes()))
+}
+
+/// Encoding declared by a `<meta charset>` or `<meta http-equiv="Content-Type">` tag
+///
+/// Only the first 1024 bytes are scanned, skipping comments, as browsers do.
+/// A UTF-16 declaration means UTF-8, since the tag itself was readable as ASCII.
+pub fn sniff_meta(body: &[u8]) -> Option<&'static Encoding> {
+    let head = &body[..body.len().min(PRESCAN_LIMIT)];
+    let mut i = 0;
+
+    while i < head.len() {
+        if head[i..].starts_with(b"<!--") {
+            i = find(head, i + 4, b"-->").map_or(head.len(), |end| end + 3);
+            continue;
+        }
+        let is_meta = head.len() > i + 5
+            && head[i..i + 5].eq_ignore_ascii_case(b"<meta")
+            && (head[i + 5].is_ascii_whitespace() || head[i + 5] == b'/');
+        if !is_meta {
+            i += 1;
+            continue;
+        }
+
+        let (attributes, end) = parse_attributes(head, i + 5);
+        i = end;
+        let attribute = |name: &str| attributes.iter().find(|(attribute, _)| attribute == name).map(|(_, value)| value.as_str());
+        let label = match attribute("charset") {
+            Some(charset) => Some(charset),
+            None if attribute("http-equiv").is_some_and(|value| value.eq_ignore_ascii_case("content-type")) => {
+                attribute("content").and_then(charset_param)
+            }
+            None => None,
+        };
+
+        if let Some(encoding) = label.and_then(|label| Encoding::for_label(label.trim().as_bytes())) {
+            return Some(match encoding.name() {
+                "UTF-16LE" | "UTF-16BE" => UTF_8,
+                "x-user-defined" => WINDOWS_1252,
+                _ => encoding,
+            });
+        }
+    }
+
+    None
+}
+
+/// Lowercased attribute names and their values, up to the closing `>`
+fn parse_attributes(input: &[u8], mut i: usize) -> (Vec<(String, String)>, usize) {
+    let mut attributes = Vec::new();
+
+    loop {
+        while i < input.len() && (input[i].is_ascii_whitespace() || input[i] == b'/') {
+            i += 1;
+        }
+        if i >= input.len() || input[i] == b'>' {
+            return (attributes, i + 1);
+        }
+
+        let start = i;
+        while i < input.len() && !input[i].is_ascii_whitespace() && !b"=>/".contains(&input[i]) {
+            i += 1;
+        }
+        let name = String::from_utf8_lossy(&input[start..i]).to_ascii_lowercase();
+        while i < input.len() && input[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let mut value = String::new();
+        if input.get(i) == Some(&b'=') {
+            i += 1;
+            while i < input.len() && input[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            let start = i;
+            match input.get(i) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let end = input[i + 1..].iter().position(|&b| b == quote).map_or(input.len(), |end| i + 1 + end);
+                    value = String::from_utf8_lossy(&input[start + 1..end]).into_owned();
+                    i = end + 1;
+                }
+                _ => {
+                    while i < input.len() && !input[i].is_ascii_whitespace() && input[i] != b'>' {
+                        i += 1;
+                    }
+                    value = String::from_utf8_lossy(&input[start..i]).into_owned();
+                }
+            }
+        }
+        attributes.push((name, value));
+    }
+}
+
+fn find(haystack: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    haystack.get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+/// Resolve the encoding of a body
+///
+/// A byte order mark wins, then the Content-Type charset, then a `<meta>`
+/// declaration for HTML (or untyped) bodies. UTF-8 is the fallback.
+pub fn detect(content_type: Option<&str>, body: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type.and_then(from_content_type) {
+        return encoding;
+    }
+
+    let is_html = content_type.is_none_or(|content_type| {
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        media_type.eq_ignore_ascii_case("text/html") || media_type.eq_ignore_ascii_case("application/xhtml+xml")
+    });
+    if is_html && let Some(encoding) = sniff_meta(body) {
+        return encoding;
+    }
+    UTF_8
+}
+
+/// Decode a body to UTF-8 text, replacing malformed sequences
+///
+/// Returns the encoding used and whether anything had to be replaced. A
+/// byte order mark is removed.
+pub fn decode(content_type: Option<&str>, body: &[u8]) -> (String, &'static Encoding, bool) {
+    let (text, encoding, malformed) = detect(content_type, body).decode(body);
+    (text.into_owned(), encoding, malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::HttpResponse;
+    use encoding_rs::{GBK, SHIFT_JIS, UTF_16LE};
+
+    #[test]
+    fn test_detect_precedence() {
+        let meta = b"<!-- <meta charset=gbk> --><html><head><META http-equiv='Content-Type' content=\"text/html; charset=Shift_JIS\"></head>";
+        assert_eq!(sniff_meta(meta), Some(SHIFT_JIS));
+        assert_eq!(sniff_meta(b"<meta name=x charset = \"utf-16\">"), Some(UTF_8));
+        assert_eq!(sniff_meta(b"<metadata charset=gbk>"), None);
+
+        assert_eq!(detect(None, meta), SHIFT_JIS);
+        assert_eq!(detect(Some("text/html"), meta), SHIFT_JIS);
+        assert_eq!(detect(Some("text/html; charset=\"GBK\""), meta), GBK);
+        assert_eq!(detect(Some("text/plain"), meta), UTF_8);
+        assert_eq!(detect(Some("text/html; charset=gbk"), b"\xff\xfe<\x00"), UTF_16LE);
+        assert_eq!(detect(Some("text/html; charset=latin1"), b""), WINDOWS_1252);
+    }
+
+    #[test]
+    fn test_response_text_decodes_legacy_encodings() {
+        let shift_jis = HttpResponse::parse(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=Shift_JIS\r\nContent-Length: 4\r\n\r\n\x93\xfa\x96\x7b").unwrap();
+        assert_eq!(shift_jis.text().unwrap(), "日本");
+
+        let gbk = HttpResponse::parse(b"HTTP/1.1 200 OK\r\nContent-Length: 27\r\n\r\n<meta charset=\"gb2312\">\xd6\xd0\xce\xc4").unwrap();
+        assert_eq!(gbk.charset(), GBK);
+        assert!(gbk.text().unwrap().ends_with("中文"));
+
+        let windows_1252 = HttpResponse::parse(b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=windows-1252\r\nContent-Length: 6\r\n\r\ncaf\xe9 \x80").unwrap();
+        assert_eq!(windows_1252.text().unwrap(), "café €");
+
+        let malformed = HttpResponse::parse(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nab\xff").unwrap();
+        assert!(malformed.text().is_err());
+        assert_eq!(malformed.text_lossy(), "ab\u{fffd}");
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{Read, Result};
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+
+use crate::body::BodyTooLarge;
+use crate::error::HttpError;
+
+/// Value advertised in Accept-Encoding for the codings we can decode
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// Content codings supported by the response pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+impl ContentCoding {
+    /// Parse a single coding token from a Content-Encoding header
+    pub fn from_token(token: &str) -> Result<Self> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(ContentCoding::Identity),
+            "gzip" | "x-gzip" => Ok(ContentCoding::Gzip),
+            "deflate" => Ok(ContentCoding::Deflate),
+            "br" => Ok(ContentCoding::Brotli),
+            other => Err(HttpError::protocol(format!("Unsupported content encoding: {}", other))),
+        }
+    }
+
+    /// Undo this coding
+    pub fn decode(self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decode_limited(data, None)
+    }
+
+    /// Undo this coding, failing with [`BodyTooLarge`] past `max_size` decoded bytes
+    pub fn decode_limited(self, data: &[u8], max_size: Option<u64>) -> Result<Vec<u8>> {
+        let reader: Box<dyn Read + '_> = match self {
+            ContentCoding::Identity => Box::new(data),
+            ContentCoding::Gzip => Box::new(MultiGzDecoder::new(data)),
+            // "deflate" should be zlib-wrapped, but some servers send raw deflate
+            ContentCoding::Deflate if Self::has_zlib_header(data) => Box::new(ZlibDecoder::new(data)),
+            ContentCoding::Deflate => Box::new(DeflateDecoder::new(data)),
+            ContentCoding::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+        };
+
+        // Read one byte past the limit to tell "exactly at" from "over"
+        let mut decoded = Vec::new();
+        let read_limit = max_size.map_or(u64::MAX, |max| max.saturating_add(1));
+        reader.take(read_limit).read_to_end(&mut decoded).map_err(|e| HttpError::Protocol {
+            message: format!("Invalid {:?} body: {}", self, e),
+            source: Some(e.into()),
+        })?;
+
+        match max_size {
+            Some(max) if decoded.len() as u64 > max => Err(BodyTooLarge::error(max)),
+            _ => Ok(decoded),
+        }
+    }
+
+    fn has_zlib_header(data: &[u8]) -> bool {
+        data.len() >= 2
+            && data[0] & 0x0F == 8
+            && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0
+    }
+}
+
+/// Decode a body given the Content-Encoding header value
+///
+/// Codings are listed in the order they were applied, so they are undone
+/// from last to first.
+pub fn decode_body(body: &[u8], content_encoding: &str) -> Result<Vec<u8>> {
+    decode_body_limited(body, content_encoding, None)
+}
+
+/// [`decode_body`] with a cap on every decoded stage, guarding against decompression bombs
+pub fn decode_body_limited(body: &[u8], content_encoding: &str, max_size: Option<u64>) -> Result<Vec<u8>> {
+    let codings = content_encoding.split(',')
+        .map(ContentCoding::from_token)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut decoded = body.to_vec();
+    for coding in codings.into_iter().rev() {
+        decoded = coding.decode_limited(&decoded, max_size)?;
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_gzip_and_brotli() {
+        assert_eq!(decode_body(&gzip(b"hello gzip"), "gzip").unwrap(), b"hello gzip");
+
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+            encoder.write_all(b"hello brotli").unwrap();
+        }
+        assert_eq!(decode_body(&compressed, "br").unwrap(), b"hello brotli");
+    }
+
+    #[test]
+    fn test_stacked_encodings() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"stacked").unwrap();
+        let body = gzip(&zlib.finish().unwrap());
+
+        // deflate was applied first, then gzip
+        assert_eq!(decode_body(&body, "deflate, gzip").unwrap(), b"stacked");
+    }
+
+    #[test]
+    fn test_decoded_size_limit() {
+        let bomb = gzip(&[0u8; 100_000]);
+
+        let error = decode_body_limited(&bomb, "gzip", Some(1000)).unwrap_err();
+        assert_eq!(BodyTooLarge::find(&error), Some(&BodyTooLarge { limit: 1000 }));
+        assert_eq!(decode_body_limited(&bomb, "gzip", Some(100_000)).unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn test_unknown_coding_is_error() {
+        assert!(decode_body(b"data", "zstd").is_err());
+        assert_eq!(decode_body(b"data", "identity").unwrap(), b"data");
+    }
+
+    #[test]
+    fn test_decode_errors_are_protocol_errors() {
+        let unsupported = decode_body(b"data", "zstd").unwrap_err();
+        assert!(matches!(HttpError::find(&unsupported), Some(HttpError::Protocol { .. })));
+
+        let mut corrupt = gzip(b"hello gzip");
+        corrupt.truncate(corrupt.len() - 4);
+        let error = decode_body(&corrupt, "gzip").unwrap_err();
+        assert!(matches!(HttpError::find(&error), Some(HttpError::Protocol { source: Some(_), .. })));
+        assert!(!HttpError::find(&error).unwrap().is_transient());
+    }
+}
@@ -1,13 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::hash::Hash;
 use std::fmt::Debug;
+use std::net::{IpAddr, SocketAddr};
 
-use tokio::sync::{Semaphore, Mutex as TokioMutex};
+use tokio::sync::Mutex as TokioMutex;
+use tokio::sync::watch;
 use tokio::time::{sleep, timeout};
 
-use crate::http_client::{HttpClient, HttpStream};
+use crate::error::HttpError;
+use crate::http_client::{AsyncHttpStream, HttpClient, MachineHttpPriority};
+use crate::http2::Http2Connection;
+use crate::proxy::Proxy;
+use crate::scheduler::{PriorityPermit, PriorityPermits};
+use crate::timing::{Phase, Timings};
 use crate::dns::DnsResolver;
 
 const DEFAULT_MAX_CONNECTIONS: usize = 100;
@@ -19,25 +26,81 @@ pub struct ConnectionKey {
     pub scheme: String,
     pub host: String,
     pub port: u16,
+    /// Proxy the connection is tunnelled through, if any
+    pub proxy: Option<Proxy>,
+    /// TLS configuration the connection was opened with, as numbered by
+    /// `HttpClient::set_tls_config`; 0 for the default and for plain HTTP
+    pub tls_config: u64,
 }
 
+impl ConnectionKey {
+    pub fn new(scheme: &str, host: &str, port: u16, proxy: Option<Proxy>) -> Self {
+        ConnectionKey {
+            scheme: scheme.to_string(),
+            host: host.to_string(),
+            port,
+            proxy,
+            tls_config: 0,
+        }
+    }
+}
+
+/// An idle connection waiting in the pool
 struct ConnectionPoolEntry {
-    stream: HttpStream,
+    stream: AsyncHttpStream,
     created_at: Instant,
     last_used: Instant,
-    in_use: bool,
 }
 
 struct ConnectionPoolInner {
     connections: HashMap<ConnectionKey, Vec<ConnectionPoolEntry>>,
+    /// At most one multiplexed connection per key, shared by all callers
+    http2_connections: HashMap<ConnectionKey, Http2Connection>,
     idle_timeout: Duration,
     max_connections: usize,
     total_connections: usize,
+    total_in_use: usize,
+}
+
+/// Connections being opened to hosts that may speak HTTP/2
+#[derive(Default)]
+struct Http2Dials {
+    /// One handshake in flight per key; it closes when the handshake settles
+    pending: HashMap<ConnectionKey, watch::Receiver<()>>,
+    /// Keys whose server answered ALPN with HTTP/1.1, so callers dial in parallel
+    http1_only: HashSet<ConnectionKey>,
+}
+
+/// What a caller that needs a new connection should do
+enum Dial<'a> {
+    /// Open the connection; waiters are released when the slot is dropped
+    Lead(PendingDial<'a>),
+    /// Wait for the handshake in flight, then look again
+    Wait(watch::Receiver<()>),
+    /// The host only speaks HTTP/1.1; open a connection of our own
+    Http1,
+}
+
+/// The pending slot held by the caller opening a connection for a key
+struct PendingDial<'a> {
+    dials: &'a Mutex<Http2Dials>,
+    key: ConnectionKey,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for PendingDial<'_> {
+    fn drop(&mut self) {
+        // Dropping the sender afterwards wakes every waiter
+        if let Ok(mut dials) = self.dials.lock() {
+            dials.pending.remove(&self.key);
+        }
+    }
 }
 
 pub struct ConnectionPool {
     inner: Arc<TokioMutex<ConnectionPoolInner>>,
-    semaphore: Arc<Semaphore>,
+    http2_dials: Mutex<Http2Dials>,
+    permits: PriorityPermits,
     http_client: HttpClient,
     dns_resolver: Arc<Mutex<DnsResolver>>,
     connection_timeout: Duration,
@@ -53,110 +116,258 @@ impl ConnectionPool {
         ConnectionPool {
             inner: Arc::new(TokioMutex::new(ConnectionPoolInner {
                 connections: HashMap::new(),
+                http2_connections: HashMap::new(),
                 idle_timeout,
                 max_connections,
                 total_connections: 0,
+                total_in_use: 0,
             })),
-            semaphore: Arc::new(Semaphore::new(max_connections)),
+            http2_dials: Mutex::new(Http2Dials::default()),
+            permits: PriorityPermits::new(max_connections),
             http_client,
             dns_resolver: Arc::new(Mutex::new(dns_resolver)),
             connection_timeout,
+            max_connections,
         }
     }
     
+    /// Check out an HTTP/1.1 connection (through the pool client's proxy, if set)
     pub async fn get_connection(&self, scheme: &str, host: &str, port: u16) -> tokio::io::Result<ConnectionGuard<'_>> {
-        let key = ConnectionKey {
-            scheme: scheme.to_string(),
-            host: host.to_string(),
-            port,
-        };
-        
-        // Acquire semaphore to ensure we don't exceed max connections
-        let permit = self.semaphore.acquire().await.unwrap();
+        let mut key = ConnectionKey::new(scheme, host, port, self.http_client.proxy().cloned());
+        if scheme == "https" {
+            key.tls_config = self.http_client.tls_config_id();
+        }
         
-        // Try to find an idle connection
-        let mut found_idle = false;
-        let mut stream_ref: Option<&mut HttpStream> = None;
-        let mut key_clone = key.clone();
+        // Acquire a permit to ensure we don't exceed max connections
+        let permit = self.permits.acquire(None).await;
         
-        { 
-            let mut inner = self.inner.lock().await;
-            let idle_timeout = inner.idle_timeout;
-            
-            if let Some(entries) = inner.connections.get_mut(&key) {
-                // Find an idle entry
-                for entry in entries.iter_mut() {
-                    if !entry.in_use && entry.last_used.elapsed() < idle_timeout {
-                        entry.in_use = true;
-                        entry.last_used = Instant::now();
-                        stream_ref = Some(&mut entry.stream);
-                        found_idle = true;
+        match self.take_idle(&key).await {
+            Some(entry) => Ok(self.guard(key, entry.stream, entry.created_at, permit, true)),
+            None => {
+                let (stream, _) = self.open_stream(&key, false, &self.http_client).await?;
+                self.track_new_connection().await;
+                Ok(self.guard(key, stream, Instant::now(), permit, false))
+            }
+        }
+    }
+    
+    /// Check out a connection for one request, preferring HTTP/2
+    ///
+    /// An existing HTTP/2 connection for the key is shared without taking a
+    /// permit. Otherwise an idle HTTP/1.1 connection is reused, or a new one
+    /// is opened; for HTTPS the client offers h2 via ALPN when enabled, and
+    /// a negotiated HTTP/2 connection is kept for every later request.
+    pub async fn checkout(&self, key: ConnectionKey) -> tokio::io::Result<PooledConnection<'_>> {
+        let (connection, _) = self.checkout_timed(key, &self.http_client, None).await?;
+        Ok(connection)
+    }
+    
+    /// [`ConnectionPool::checkout`] on behalf of `client`, under its per-phase deadlines
+    ///
+    /// New connections are opened with `client`'s connect timeout, TLS
+    /// settings and HTTP/2 preference rather than the pool's own client.
+    /// Returns how long DNS, connect and TLS took; all zero for a reused connection.
+    /// When every permit is taken, waiters are served by `priority` (see
+    /// [`PriorityPermits`]).
+    ///
+    /// Concurrent first requests to a host that may speak HTTP/2 open a
+    /// single connection: later callers wait for the handshake in flight and
+    /// share its HTTP/2 connection, or open their own if it was HTTP/1.1.
+    pub async fn checkout_timed(&self, key: ConnectionKey, client: &HttpClient, priority: Option<MachineHttpPriority>) -> tokio::io::Result<(PooledConnection<'_>, Timings)> {
+        let http2 = key.scheme == "https" && client.http2_enabled();
+        let mut _dial = None;
+        if http2 {
+            loop {
+                if let Some(connection) = self.http2_connection(&key).await {
+                    return Ok((PooledConnection::Http2 { connection, reused: true }, Timings::default()));
+                }
+                match self.begin_dial(&key) {
+                    Dial::Lead(pending) => {
+                        _dial = Some(pending);
                         break;
                     }
+                    Dial::Wait(mut done) => {
+                        // Errors once the leader drops its slot, which is all we wait for
+                        let _ = done.changed().await;
+                    }
+                    Dial::Http1 => break,
                 }
             }
         }
         
-        if found_idle {
-            return Ok(ConnectionGuard {
-                pool: self,
-                key: key_clone,
-                stream_ref: stream_ref.unwrap(),
-                permit: Some(permit),
-            });
-        }
+        let permit = self.permits.acquire(priority).await;
         
-        // No idle connection, create a new one
-        let host_clone = host.to_string();
-        let host_clone_for_tls = host_clone.clone();
-        let scheme_clone = scheme.to_string();
-        let dns_resolver = self.dns_resolver.clone();
-        let http_client = self.http_client.clone();
-        let connection_timeout = self.connection_timeout;
+        if let Some(entry) = self.take_idle(&key).await {
+            let guard = self.guard(key, entry.stream, entry.created_at, permit, true);
+            return Ok((PooledConnection::Http1(Box::new(guard)), Timings::default()));
+        }
         
-        // Resolve DNS in a blocking context
-        let ip = tokio::task::spawn_blocking(move || {
-            let mut resolver = dns_resolver.lock().unwrap();
-            resolver.resolve_ip(&host_clone)
-        }).await??;
+        let timeouts = client.timeouts();
+        let (stream, mut timings) = self.open_stream(&key, http2, client).await?;
         
-        // Create connection with timeout
-        let stream = timeout(connection_timeout, async move {
-            match scheme_clone.as_str() {
-                "http" => http_client.connect_http((ip, port)),
-                "https" => http_client.connect_https((ip, port), &host_clone_for_tls),
-                _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unsupported scheme: {}", scheme_clone))),
+        if !stream.is_http2() {
+            if http2 {
+                self.http2_dials.lock().unwrap().http1_only.insert(key.clone());
             }
-        }).await??;
+            self.track_new_connection().await;
+            let guard = self.guard(key, stream, Instant::now(), permit, false);
+            return Ok((PooledConnection::Http1(Box::new(guard)), timings));
+        }
+        
+        // HTTP/2 connections are shared, so they do not hold a permit
+        drop(permit);
+        let peer_certificate = stream.peer_certificate();
+        let connection = timeouts.timed(Phase::Tls, &mut timings.tls, Http2Connection::handshake(stream)).await?
+            .with_peer_certificate(peer_certificate);
         
-        // Add the new connection to the pool
-        { 
-            let mut inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await;
+        if let Some(existing) = inner.http2_connections.get(&key)
+            && !existing.is_closed()
+        {
+            // Another caller raced us to the same host; keep a single connection
+            return Ok((PooledConnection::Http2 { connection: existing.clone(), reused: true }, timings));
+        }
+        if inner.http2_connections.insert(key, connection.clone()).is_none() {
             inner.total_connections += 1;
-            
-            let entry = ConnectionPoolEntry {
-                stream,
-                created_at: Instant::now(),
-                last_used: Instant::now(),
-                in_use: true,
-            };
-            
-            let entries = inner.connections.entry(key.clone()).or_insert_with(Vec::new);
-            entries.push(entry);
         }
-        
-        // Get the new connection from the pool
+        Ok((PooledConnection::Http2 { connection, reused: false }, timings))
+    }
+    
+    /// Claim the key's pending slot, or learn whose handshake to wait for
+    fn begin_dial(&self, key: &ConnectionKey) -> Dial<'_> {
+        let mut dials = self.http2_dials.lock().unwrap();
+        if dials.http1_only.contains(key) {
+            return Dial::Http1;
+        }
+        if let Some(done) = dials.pending.get(key) {
+            return Dial::Wait(done.clone());
+        }
+        let (sender, done) = watch::channel(());
+        dials.pending.insert(key.clone(), done);
+        Dial::Lead(PendingDial { dials: &self.http2_dials, key: key.clone(), _done: sender })
+    }
+    
+    /// Forget an HTTP/2 connection that failed (e.g. after GOAWAY)
+    pub async fn remove_http2(&self, key: &ConnectionKey, connection: &Http2Connection) {
+        let mut inner = self.inner.lock().await;
+        if inner.http2_connections.get(key).is_some_and(|existing| existing.ptr_eq(connection)) {
+            inner.http2_connections.remove(key);
+            inner.total_connections = inner.total_connections.saturating_sub(1);
+        }
+    }
+    
+    /// Live HTTP/2 connection for a key, dropping it if it has closed
+    async fn http2_connection(&self, key: &ConnectionKey) -> Option<Http2Connection> {
         let mut inner = self.inner.lock().await;
-        let entries = inner.connections.get_mut(&key).unwrap();
-        let stream_ref = &mut entries.last_mut().unwrap().stream;
+        match inner.http2_connections.get(key) {
+            Some(connection) if !connection.is_closed() => Some(connection.clone()),
+            Some(_) => {
+                inner.http2_connections.remove(key);
+                inner.total_connections = inner.total_connections.saturating_sub(1);
+                None
+            }
+            None => None,
+        }
+    }
+    
+    /// Pop the most recently used idle connection that has not expired
+    async fn take_idle(&self, key: &ConnectionKey) -> Option<ConnectionPoolEntry> {
+        let mut inner = self.inner.lock().await;
+        let idle_timeout = inner.idle_timeout;
+        let mut expired = 0;
+        let mut reused = None;
+        
+        if let Some(entries) = inner.connections.get_mut(key) {
+            // Most recently used connections are at the back
+            while let Some(entry) = entries.pop() {
+                if entry.last_used.elapsed() < idle_timeout {
+                    reused = Some(entry);
+                    break;
+                }
+                expired += 1;
+            }
+        }
+        
+        inner.total_connections -= expired;
+        if reused.is_some() {
+            inner.total_in_use += 1;
+        }
+        reused
+    }
+    
+    /// Open a new connection for `client`, directly or through the key's proxy, timing each phase
+    async fn open_stream(&self, key: &ConnectionKey, alpn: bool, client: &HttpClient) -> tokio::io::Result<(AsyncHttpStream, Timings)> {
+        let timeouts = client.timeouts();
+        if key.scheme != "http" && key.scheme != "https" {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unsupported scheme: {}", key.scheme)));
+        }
         
-        Ok(ConnectionGuard {
+        // Create connection with timeout; the async connect can be cancelled when it expires
+        timeout(self.connection_timeout, async {
+            let mut timings = Timings::default();
+            let tcp_stream = match &key.proxy {
+                None => {
+                    let ip = timeouts.timed(Phase::Dns, &mut timings.dns, self.resolve(&key.host)).await?;
+                    timeouts.timed(Phase::Connect, &mut timings.connect, client.connect_async((ip, key.port))).await
+                        .map_err(|e| HttpError::connect(&SocketAddr::new(ip, key.port).to_string(), e))?
+                }
+                Some(proxy) => {
+                    let (proxy_ip, target_ip) = timeouts.timed(Phase::Dns, &mut timings.dns, async {
+                        let proxy_ip = self.resolve(proxy.host()).await?;
+                        let target_ip = if proxy.resolves_locally() {
+                            Some(self.resolve(&key.host).await?)
+                        } else {
+                            None
+                        };
+                        Ok((proxy_ip, target_ip))
+                    }).await?;
+                    timeouts.timed(Phase::Connect, &mut timings.connect, async {
+                        let mut tcp_stream = client.connect_async((proxy_ip, proxy.port())).await?;
+                        proxy.tunnel(&mut tcp_stream, &key.host, key.port, target_ip).await?;
+                        Ok(tcp_stream)
+                    }).await
+                        .map_err(|e| HttpError::connect(&SocketAddr::new(proxy_ip, proxy.port()).to_string(), e))?
+                }
+            };
+            
+            let stream = if key.scheme == "https" {
+                timeouts.timed(Phase::Tls, &mut timings.tls, client.connect_tls_async(tcp_stream, &key.host, alpn)).await?
+            } else {
+                AsyncHttpStream::Plain(tokio::io::BufReader::new(tcp_stream))
+            };
+            Ok((stream, timings))
+        }).await
+            .map_err(|_| HttpError::timeout(format!("Connecting to {}:{} timed out", key.host, key.port)))?
+    }
+    
+    /// Resolve a host name in a blocking context
+    async fn resolve(&self, host: &str) -> tokio::io::Result<IpAddr> {
+        let dns_resolver = self.dns_resolver.clone();
+        let host = host.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut resolver = dns_resolver.lock().unwrap();
+            resolver.resolve_ip(&host)
+        }).await?
+    }
+    
+    /// Account for a new HTTP/1.1 connection that is about to be checked out
+    async fn track_new_connection(&self) {
+        let mut inner = self.inner.lock().await;
+        inner.total_connections += 1;
+        inner.total_in_use += 1;
+    }
+    
+    /// Wrap a checked-out HTTP/1.1 stream; it joins the idle list when the guard is dropped
+    fn guard<'a>(&'a self, key: ConnectionKey, stream: AsyncHttpStream, created_at: Instant, permit: PriorityPermit<'a>, reused: bool) -> ConnectionGuard<'a> {
+        ConnectionGuard {
             pool: self,
             key,
-            stream_ref,
+            stream: Some(stream),
+            created_at,
             permit: Some(permit),
-        })
-    } 
+            reused,
+        }
+    }
     
     pub async fn cleanup(&self) { 
         let mut inner = self.inner.lock().await; 
@@ -170,7 +381,7 @@ impl ConnectionPool {
             let original_len = entries.len(); 
             
             // Remove idle connections that exceed the timeout 
-            entries.retain(|entry| entry.in_use || (now - entry.last_used) < idle_timeout); 
+            entries.retain(|entry| (now - entry.last_used) < idle_timeout); 
             
             // Count the number of connections removed 
             let removed = original_len - entries.len(); 
@@ -189,6 +400,12 @@ impl ConnectionPool {
         for key in keys_to_remove { 
             inner.connections.remove(&key); 
         } 
+        
+        // Drop HTTP/2 connections the peer has closed
+        let open_http2 = inner.http2_connections.len();
+        inner.http2_connections.retain(|_, connection| !connection.is_closed());
+        let closed_http2 = open_http2 - inner.http2_connections.len();
+        inner.total_connections = inner.total_connections.saturating_sub(closed_http2);
     } 
     
     pub async fn run_cleanup_task(self: Arc<Self>, interval: Duration) { 
@@ -200,34 +417,47 @@ impl ConnectionPool {
     
     pub async fn get_stats(&self) -> PoolStats { 
         let inner = self.inner.lock().await; 
-        let mut total_idle = 0; 
-        let mut total_in_use = 0; 
-        
-        for entries in inner.connections.values() { 
-            for entry in entries { 
-                if entry.in_use { 
-                    total_in_use += 1; 
-                } else { 
-                    total_idle += 1; 
-                } 
-            } 
-        } 
+        let total_idle = inner.connections.values().map(Vec::len).sum(); 
         
         PoolStats { 
             total_connections: inner.total_connections, 
             total_idle, 
-            total_in_use, 
+            total_in_use: inner.total_in_use, 
             max_connections: inner.max_connections, 
             idle_timeout: inner.idle_timeout, 
             connection_count: inner.connections.len(), 
+            http2_connections: inner.http2_connections.len(),
         } 
     } 
     
     pub async fn close_all_connections(&self) { 
         let mut inner = self.inner.lock().await; 
         inner.connections.clear(); 
-        inner.total_connections = 0; 
+        inner.http2_connections.clear();
+        inner.total_connections = inner.total_in_use; 
+        self.http2_dials.lock().unwrap().http1_only.clear();
     } 
+    
+    /// Maximum number of concurrently checked-out connections
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+    
+    /// Put a checked-out connection back (or forget it when it is no longer usable)
+    fn release(inner: &mut ConnectionPoolInner, key: ConnectionKey, stream: Option<AsyncHttpStream>, created_at: Instant) {
+        inner.total_in_use = inner.total_in_use.saturating_sub(1);
+        
+        match stream {
+            Some(stream) => {
+                inner.connections.entry(key).or_default().push(ConnectionPoolEntry {
+                    stream,
+                    created_at,
+                    last_used: Instant::now(),
+                });
+            }
+            None => inner.total_connections = inner.total_connections.saturating_sub(1),
+        }
+    }
 } 
 
 #[derive(Debug, Clone)] 
@@ -238,22 +468,48 @@ pub struct PoolStats {
     pub max_connections: usize, 
     pub idle_timeout: Duration, 
     pub connection_count: usize, 
+    /// Shared HTTP/2 connections (counted in `total_connections`)
+    pub http2_connections: usize,
 } 
 
+/// A connection handed out by [`ConnectionPool::checkout`]
+pub enum PooledConnection<'a> {
+    /// Exclusive HTTP/1.1 connection, returned to the pool on drop
+    Http1(Box<ConnectionGuard<'a>>),
+    /// Shared HTTP/2 connection; `reused` is false if it was just opened
+    Http2 { connection: Http2Connection, reused: bool },
+}
+
+/// A connection checked out of the pool
+///
+/// The guard owns the stream while it is in use and hands it back to the
+/// pool when dropped, unless it was discarded.
 pub struct ConnectionGuard<'a> {
     pub pool: &'a ConnectionPool,
     pub key: ConnectionKey,
-    pub stream_ref: &'a mut HttpStream,
-    pub permit: Option<tokio::sync::SemaphorePermit<'a>>,
+    stream: Option<AsyncHttpStream>,
+    created_at: Instant,
+    pub permit: Option<PriorityPermit<'a>>,
+    reused: bool,
 }
 
 impl<'a> ConnectionGuard<'a> {
-    pub fn get_mut(&mut self) -> Option<&mut HttpStream> {
-        Some(self.stream_ref)
+    pub fn get_mut(&mut self) -> Option<&mut AsyncHttpStream> {
+        self.stream.as_mut()
     }
     
     pub fn is_valid(&self) -> bool {
-        true
+        self.stream.is_some()
+    }
+    
+    /// Whether this connection was reused from the idle list
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+    
+    /// Close the connection instead of returning it to the pool
+    pub fn discard(&mut self) {
+        self.stream = None;
     }
 }
 
@@ -262,21 +518,19 @@ impl<'a> Drop for ConnectionGuard<'a> {
         // Release the connection back to the pool
         let pool = self.pool.inner.clone();
         let key = self.key.clone();
+        let stream = self.stream.take();
+        let created_at = self.created_at;
         
-        tokio::spawn(async move {
-            let mut inner = pool.lock().await;
-            if let Some(entries) = inner.connections.get_mut(&key) {
-                for entry in entries.iter_mut() {
-                    if entry.in_use {
-                        entry.in_use = false;
-                        entry.last_used = Instant::now();
-                        break;
-                    }
-                }
-            }
-        });
+        if let Ok(mut inner) = pool.try_lock() {
+            ConnectionPool::release(&mut inner, key, stream, created_at);
+        } else {
+            tokio::spawn(async move {
+                let mut inner = pool.lock().await;
+                ConnectionPool::release(&mut inner, key, stream, created_at);
+            });
+        }
         
-        // Release the semaphore permit
+        // Release the connection permit
         drop(self.permit.take());
     }
 } 
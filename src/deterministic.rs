@@ -51,12 +51,12 @@ impl DeterministicTimestamp {
             // Ensure monotonicity by updating base_ms if needed
             let mut current_base = self.base_ms.load(Ordering::Relaxed);
             while now_ms > current_base {
-                if let Ok(_) = self.base_ms.compare_exchange(
+                if self.base_ms.compare_exchange(
                     current_base,
                     now_ms,
                     Ordering::Relaxed,
                     Ordering::Relaxed,
-                ) {
+                ).is_ok() {
                     current_base = now_ms;
                 } else {
                     // If compare_exchange failed, reload current_base and try again
@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use std::time::{Duration, SystemTime}; 
 use std::net::IpAddr; 
 
+use crate::error::{DnsFailure, HttpError}; 
+
 const DNS_PORT: u16 = 53; 
 const DNS_TIMEOUT: Duration = Duration::from_secs(5); 
 
@@ -110,10 +112,9 @@ impl DnsResolver {
     pub fn query(&mut self, domain: &str, record_type: DnsRecordType) -> Result<Vec<DnsRecord>> { 
         // Check cache first 
         let cache_key = format!("{}:{:?}", domain, record_type); 
-        if let Some(entry) = self.cache.get(&cache_key) { 
-            if SystemTime::now() < entry.expires_at { 
-                return Ok(entry.records.clone()); 
-            } 
+        if let Some(entry) = self.cache.get(&cache_key) 
+            && SystemTime::now() < entry.expires_at { 
+            return Ok(entry.records.clone()); 
         } 
         
         // Create DNS query
@@ -122,16 +123,20 @@ impl DnsResolver {
         // Send query 
         self.socket.send_to(&query, self.dns_server)?; 
         
-        // Receive response 
+        // Receive response; the socket's read timeout surfaces as WouldBlock or TimedOut 
         let mut buffer = [0; 512]; 
-        let (size, _) = self.socket.recv_from(&mut buffer)?; 
+        let (size, _) = self.socket.recv_from(&mut buffer).map_err(|e| match e.kind() { 
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => HttpError::dns(domain, DnsFailure::Timeout, Some(e)), 
+            _ => e, 
+        })?; 
         
         // Parse response 
-        let response = self.parse_response(&buffer[..size])?; 
+        let response = self.parse_response(&buffer[..size]) 
+            .map_err(|e| HttpError::dns(domain, DnsFailure::Malformed, Some(e)))?; 
         
         // Check response status 
         if response.rcode != 0 { 
-            return Err(Error::new(ErrorKind::Other, format!("DNS query failed with rcode: {}", response.rcode))); 
+            return Err(HttpError::dns(domain, DnsFailure::from_rcode(response.rcode), None)); 
         } 
         
         // Filter records of requested type 
@@ -141,7 +146,7 @@ impl DnsResolver {
             .collect(); 
         
         if records.is_empty() { 
-            return Err(Error::new(ErrorKind::NotFound, "No records found")); 
+            return Err(HttpError::dns(domain, DnsFailure::NoRecords, None)); 
         } 
         
         // Cache the results 
@@ -157,21 +162,35 @@ impl DnsResolver {
     } 
     
     pub fn resolve_ip(&mut self, domain: &str) -> Result<IpAddr> { 
+        // IP literals need no lookup 
+        if let Ok(ip) = domain.parse::<IpAddr>() { 
+            return Ok(ip); 
+        } 
+        
         // Try A record (IPv4) first 
-        if let Ok(records) = self.query(domain, DnsRecordType::A) { 
-            if let DnsRecordData::A(ip) = &records[0].data { 
-                return Ok(*ip); 
-            } 
+        let ipv4_error = match self.query(domain, DnsRecordType::A) { 
+            Ok(records) => match &records[0].data { 
+                DnsRecordData::A(ip) => return Ok(*ip), 
+                _ => HttpError::dns(domain, DnsFailure::NoRecords, None), 
+            }, 
+            Err(e) => e, 
+        }; 
+        
+        // A name that does not exist has no AAAA records either 
+        if let Some(HttpError::Dns { failure: DnsFailure::NotFound, .. }) = HttpError::find(&ipv4_error) { 
+            return Err(ipv4_error); 
         } 
         
         // Try AAAA record (IPv6) if IPv4 failed 
-        if let Ok(records) = self.query(domain, DnsRecordType::AAAA) { 
-            if let DnsRecordData::AAAA(ip) = &records[0].data { 
-                return Ok(*ip); 
-            } 
+        match self.query(domain, DnsRecordType::AAAA) { 
+            Ok(records) => match &records[0].data { 
+                DnsRecordData::AAAA(ip) => Ok(*ip), 
+                _ => Err(HttpError::dns(domain, DnsFailure::NoRecords, None)), 
+            }, 
+            // Having no IPv6 address says less than why IPv4 failed 
+            Err(e) if matches!(HttpError::find(&e), Some(HttpError::Dns { failure: DnsFailure::NoRecords, .. })) => Err(ipv4_error), 
+            Err(e) => Err(e), 
         } 
-        
-        Err(Error::new(ErrorKind::NotFound, "Could not resolve IP address")) 
     } 
     
     fn create_query(&self, domain: &str, record_type: DnsRecordType) -> Result<Vec<u8>> { 
@@ -88,10 +88,7 @@ pub struct BinaryDomSerializer;
 
 impl BinaryDomSerializer {
     /// Serialize DOM node to binary format
-    pub fn serialize<W: Write>(node: &DomNode, writer: &mut W) -> io::Result<()>
-    where
-        W: Write,
-    {
+    pub fn serialize<W: Write>(node: &DomNode, writer: &mut W) -> io::Result<()> {
         // Write node type as u8
         writer.write_all(&[(node.node_type as u8)])?;
 
@@ -143,10 +140,7 @@ impl BinaryDomSerializer {
     }
 
     /// Deserialize DOM node from binary format
-    pub fn deserialize<R: Read>(reader: &mut R) -> io::Result<DomNode>
-    where
-        R: Read,
-    {
+    pub fn deserialize<R: Read>(reader: &mut R) -> io::Result<DomNode> {
         // Read node type
         let mut node_type_buf = [0; 1];
         reader.read_exact(&mut node_type_buf)?;
@@ -228,10 +222,7 @@ impl BinaryDomSerializer {
     }
 
     /// Write string to binary format with length prefix
-    fn write_string<W: Write>(s: &str, writer: &mut W) -> io::Result<()>
-    where
-        W: Write,
-    {
+    fn write_string<W: Write>(s: &str, writer: &mut W) -> io::Result<()> {
         let len = s.len() as u16;
         writer.write_all(&len.to_le_bytes())?;
         writer.write_all(s.as_bytes())?;
@@ -239,10 +230,7 @@ impl BinaryDomSerializer {
     }
 
     /// Read string from binary format with length prefix
-    fn read_string<R: Read>(reader: &mut R) -> io::Result<Option<String>>
-    where
-        R: Read,
-    {
+    fn read_string<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
         let mut len_buf = [0; 2];
         reader.read_exact(&mut len_buf)?;
         let len = u16::from_le_bytes(len_buf);
@@ -272,7 +260,7 @@ pub enum DomDiffOperation {
 }
 
 /// Changes to a DOM node for efficient diffing
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DomChanges {
     pub added_attributes: HashMap<String, String>,
     pub removed_attributes: Vec<String>,
@@ -280,17 +268,6 @@ pub struct DomChanges {
     pub children_changes: Vec<DomDiffOperation>,
 }
 
-impl Default for DomChanges {
-    fn default() -> Self {
-        DomChanges {
-            added_attributes: HashMap::new(),
-            removed_attributes: Vec::new(),
-            updated_attributes: HashMap::new(),
-            children_changes: Vec::new(),
-        }
-    }
-}
-
 /// DOM snapshot with efficient binary representation
 #[derive(Debug, Clone)]
 pub struct DomSnapshot {
@@ -325,10 +302,7 @@ impl DomSnapshot {
     }
 
     /// Serialize snapshot to binary format
-    pub fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()>
-    where
-        W: Write,
-    {
+    pub fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         // Write header
         writer.write_all(b"BIOSURF-DOM")?;
         
@@ -348,10 +322,7 @@ impl DomSnapshot {
     }
 
     /// Deserialize snapshot from binary format
-    pub fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self>
-    where
-        R: Read,
-    {
+    pub fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
         // Read header
         let mut header = [0; 11]; // "BIOSURF-DOM" is 11 bytes
         reader.read_exact(&mut header)?;
@@ -434,7 +405,7 @@ impl DomDiffer {
         }
         
         // Check for removed attributes
-        for (name, _) in &old.attributes {
+        for name in old.attributes.keys() {
             if !new.attributes.contains_key(name) {
                 changes.removed_attributes.push(name.clone());
             }
@@ -442,10 +413,10 @@ impl DomDiffer {
         
         // Check for updated attributes
         for (name, new_value) in &new.attributes {
-            if let Some(old_value) = old.attributes.get(name) {
-                if old_value != new_value {
-                    changes.updated_attributes.insert(name.clone(), new_value.clone());
-                }
+            if let Some(old_value) = old.attributes.get(name)
+                && old_value != new_value
+            {
+                changes.updated_attributes.insert(name.clone(), new_value.clone());
             }
         }
 
@@ -507,7 +478,7 @@ impl DomDiffer {
                 }
             } else if new_index >= new_children.len() {
                 // All new nodes processed, delete remaining old nodes
-                for _ in old_index..old_children.len() {
+                while old_index < old_children.len() {
                     operations.push(DomDiffOperation::DeleteNode { index: old_index });
                     old_index += 1;
                 }
@@ -528,8 +499,8 @@ impl DomDiffer {
                 } else {
                     // Different nodes, check if new node exists later in old list
                     let mut found = false;
-                    for i in old_index + 1..old_children.len() {
-                        if Self::nodes_are_similar(&old_children[i], new_node) {
+                    for (i, old_child) in old_children.iter().enumerate().skip(old_index + 1) {
+                        if Self::nodes_are_similar(old_child, new_node) {
                             // Move node from old position to new position
                             operations.push(DomDiffOperation::MoveNode {
                                 from_index: i,
@@ -596,8 +567,8 @@ pub struct DomPatchApplier;
 
 impl DomPatchApplier {
     /// Apply diff operations to a snapshot
-    pub fn apply(snapshot: &DomSnapshot, diff: &[DomDiffOperation]) -> DomSnapshot {
-        let mut new_root = snapshot.root.clone();
+    pub fn apply(snapshot: &DomSnapshot, _diff: &[DomDiffOperation]) -> DomSnapshot {
+        let new_root = snapshot.root.clone();
         // Apply operations (simplified implementation)
         // In a real implementation, we'd recursively apply the operations
         
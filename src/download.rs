@@ -0,0 +1,573 @@
+use std::io::{Error, ErrorKind, Result, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+
+use crate::http1::ResponseHead;
+use crate::http_client::{HttpClient, HttpRequest, MachineHttpPriority};
+use crate::retry::RetryPolicy;
+
+const DEFAULT_SEGMENTS: usize = 4;
+const DEFAULT_MIN_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+/// Parsed Content-Range of a 206 or 416 response (RFC 9110 §14.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    /// First and last byte positions (inclusive); `None` for "bytes */length"
+    pub range: Option<(u64, u64)>,
+    /// Length of the whole representation, if the server knows it
+    pub complete_length: Option<u64>,
+}
+
+impl ContentRange {
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid Content-Range: {}", value));
+        let (range, length) = value.trim()
+            .strip_prefix("bytes ")
+            .and_then(|spec| spec.split_once('/'))
+            .ok_or_else(invalid)?;
+
+        let complete_length = match length.trim() {
+            "*" => None,
+            length => Some(length.parse::<u64>().map_err(|_| invalid())?),
+        };
+        let range = match range.trim() {
+            "*" => None,
+            range => {
+                let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+                let first = first.parse::<u64>().map_err(|_| invalid())?;
+                let last = last.parse::<u64>().map_err(|_| invalid())?;
+                if last < first || complete_length.is_some_and(|length| last >= length) {
+                    return Err(invalid());
+                }
+                Some((first, last))
+            }
+        };
+        if range.is_none() && complete_length.is_none() {
+            return Err(invalid());
+        }
+
+        Ok(ContentRange { range, complete_length })
+    }
+
+    /// The Content-Range of a response head, if it has one
+    pub fn from_head(head: &ResponseHead) -> Result<Option<Self>> {
+        head.header("content-range").map(Self::parse).transpose()
+    }
+}
+
+/// Range header value for bytes `start..=end`, or from `start` to the end
+pub fn range_header(start: u64, end: Option<u64>) -> String {
+    match end {
+        Some(end) => format!("bytes={}-{}", start, end),
+        None => format!("bytes={}-", start),
+    }
+}
+
+/// Validator for If-Range: a strong ETag, otherwise Last-Modified
+///
+/// Weak ETags cannot be used with If-Range (RFC 9110 §13.1.5).
+pub fn if_range_validator(head: &ResponseHead) -> Option<String> {
+    match head.header("etag") {
+        Some(etag) if !etag.starts_with("W/") => Some(etag.to_string()),
+        _ => head.header("last-modified").map(str::to_string),
+    }
+}
+
+/// How [`HttpClient::download`] resumes and splits a download
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    retry_policy: RetryPolicy,
+    segments: usize,
+    min_segment_size: u64,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DownloadOptions {
+    /// Resume up to 3 times; Throughput requests use 4 segments of at least 1 MiB
+    pub fn new() -> Self {
+        DownloadOptions {
+            retry_policy: RetryPolicy::new(),
+            segments: DEFAULT_SEGMENTS,
+            min_segment_size: DEFAULT_MIN_SEGMENT_SIZE,
+        }
+    }
+
+    /// Resume attempts and backoff after a transfer breaks off
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Parallel range requests for Throughput priority (1 disables segmenting)
+    pub fn set_segments(&mut self, segments: usize) -> &mut Self {
+        self.segments = segments.max(1);
+        self
+    }
+
+    /// Never split into segments smaller than this
+    pub fn set_min_segment_size(&mut self, size: u64) -> &mut Self {
+        self.min_segment_size = size.max(1);
+        self
+    }
+}
+
+/// Outcome of a completed download
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadReport {
+    /// Size of the finished file
+    pub size: u64,
+    /// Bytes already on disk from an earlier, interrupted run
+    pub resumed_from: u64,
+    /// Parallel range requests used (1 for a sequential download)
+    pub segments: usize,
+    /// Times a broken transfer was resumed
+    pub retries: u32,
+}
+
+/// Download the request's absolute URL into `path`
+///
+/// Data goes to `<path>.part` and is renamed into place once complete. A
+/// sequential download keeps its If-Range validator in `<path>.part.validator`,
+/// so a later call picks up where an interrupted one stopped. Throughput
+/// priority requests are split into parallel segments when the server
+/// supports ranges; segments resume individually but not across calls.
+///
+/// Redirects are followed under the client's redirect policy, and segments
+/// are fetched from the final URL.
+pub async fn download(client: &HttpClient, request: &HttpRequest, path: &Path, options: &DownloadOptions) -> Result<DownloadReport> {
+    if matches!(request.machine_priority(), Some(MachineHttpPriority::Throughput))
+        && options.segments > 1
+        && let Some(report) = download_segmented(client, request, path, options).await?
+    {
+        return Ok(report);
+    }
+    download_sequential(client, request, path, options).await
+}
+
+async fn download_sequential(client: &HttpClient, request: &HttpRequest, path: &Path, options: &DownloadOptions) -> Result<DownloadReport> {
+    let part = with_suffix(path, ".part");
+    let validator_file = with_suffix(path, ".part.validator");
+
+    // Resuming without a validator could splice two versions of the resource
+    let mut validator = fs::read_to_string(&validator_file).await.ok();
+    let mut offset = match (&validator, fs::metadata(&part).await) {
+        (Some(_), Ok(metadata)) => metadata.len(),
+        _ => 0,
+    };
+    let resumed_from = offset;
+
+    let mut file = OpenOptions::new().create(true).write(true).truncate(offset == 0).open(&part).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let policy = &options.retry_policy;
+    let mut rng = policy.jitter_rng(request.is_deterministic());
+    let mut retries = 0;
+    loop {
+        match fetch_remaining(client, request, &mut file, &mut offset, &mut validator, &validator_file).await {
+            Ok(()) => break,
+            Err(e) if retries < policy.max_retries() && policy.should_retry_error(request.method(), &e) => {
+                tokio::time::sleep(policy.backoff(retries, &mut rng)).await;
+                retries += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    file.flush().await?;
+    drop(file);
+    fs::rename(&part, path).await?;
+    let _ = fs::remove_file(&validator_file).await;
+
+    Ok(DownloadReport { size: offset, resumed_from, segments: 1, retries })
+}
+
+/// One attempt: request everything from `offset` and append it to `file`
+async fn fetch_remaining(
+    client: &HttpClient,
+    request: &HttpRequest,
+    file: &mut File,
+    offset: &mut u64,
+    validator: &mut Option<String>,
+    validator_file: &Path,
+) -> Result<()> {
+    // Without a validator a resumed range could splice two versions: start over
+    if *offset > 0 && validator.is_none() {
+        restart(file, offset).await?;
+    }
+    let mut ranged = request.clone();
+    if let Some(validator) = validator.as_deref().filter(|_| *offset > 0) {
+        ranged.set_header("Range", &range_header(*offset, None));
+        ranged.set_header("If-Range", validator);
+    }
+
+    let (_, response) = client.execute_stream_following(&ranged).await?;
+    let head = response.head;
+    let mut body = response.body;
+
+    let expected_end = match head.status {
+        206 => match ContentRange::from_head(&head)?.and_then(|range| range.range) {
+            Some((first, last)) if first == *offset => Some(last + 1),
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("Partial response does not start at byte {}", offset))),
+        },
+        200 => {
+            // A full body means the resource changed or ranges are unsupported: start over
+            if *offset > 0 {
+                restart(file, offset).await?;
+            }
+            *validator = if_range_validator(&head);
+            match validator.as_deref() {
+                Some(validator) => fs::write(validator_file, validator).await?,
+                None => {
+                    let _ = fs::remove_file(validator_file).await;
+                }
+            }
+            head.header("content-length").and_then(|length| length.trim().parse::<u64>().ok())
+        }
+        // Everything we asked for is already on disk
+        416 if ContentRange::from_head(&head)?.and_then(|range| range.complete_length) == Some(*offset) => return Ok(()),
+        status => return Err(Error::other(format!("Download failed with status {} {}", status, head.status_text))),
+    };
+
+    while let Some(chunk) = body.next_chunk().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        *offset += chunk.len() as u64;
+    }
+    if expected_end.is_some_and(|end| *offset < end) {
+        return Err(Error::new(ErrorKind::UnexpectedEof, format!("Download ended at byte {}", offset)));
+    }
+    Ok(())
+}
+
+/// Empty `file` so the download starts again from the first byte
+async fn restart(file: &mut File, offset: &mut u64) -> Result<()> {
+    file.set_len(0).await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    *offset = 0;
+    Ok(())
+}
+
+/// Split the download into parallel range requests; `None` if the server cannot serve ranges
+async fn download_segmented(client: &HttpClient, request: &HttpRequest, path: &Path, options: &DownloadOptions) -> Result<Option<DownloadReport>> {
+    // A one-byte range reveals the length, the validator and range support
+    let mut probe = request.clone();
+    probe.set_header("Range", &range_header(0, Some(0)));
+    let (request, mut response) = client.execute_stream_following(&probe).await?;
+    let total = match ContentRange::from_head(&response.head)? {
+        Some(ContentRange { complete_length: Some(total), .. }) if response.head.status == 206 => total,
+        _ => return Ok(None),
+    };
+    let validator = if_range_validator(&response.head);
+    // Finish the probe so its connection can be reused by a segment
+    while let Some(chunk) = response.body.next_chunk().await {
+        chunk?;
+    }
+    drop(response);
+
+    let segments = options.segments.min((total / options.min_segment_size) as usize);
+    if segments < 2 {
+        return Ok(None);
+    }
+
+    // A sequential run's validator must not vouch for the holes of a segmented .part
+    let part = with_suffix(path, ".part");
+    match fs::remove_file(with_suffix(path, ".part.validator")).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    File::create(&part).await?.set_len(total).await?;
+
+    let segment_size = total.div_ceil(segments as u64);
+    let mut tasks = JoinSet::new();
+    for index in 0..segments as u64 {
+        let first = index * segment_size;
+        let last = ((index + 1) * segment_size).min(total) - 1;
+        let (client, request, validator, part, policy) = (client.clone(), request.clone(), validator.clone(), part.clone(), options.retry_policy.clone());
+        tasks.spawn(async move {
+            fetch_segment(&client, &request, &part, first, last, validator.as_deref(), &policy).await
+        });
+    }
+
+    let mut retries = 0;
+    while let Some(result) = tasks.join_next().await {
+        match result.map_err(Error::other).and_then(|result| result) {
+            Ok(segment_retries) => retries += segment_retries,
+            Err(e) => {
+                // The other segments are cancelled and their holes discarded
+                tasks.shutdown().await;
+                let _ = fs::remove_file(&part).await;
+                return Err(e);
+            }
+        }
+    }
+
+    fs::rename(&part, path).await?;
+    Ok(Some(DownloadReport { size: total, resumed_from: 0, segments, retries }))
+}
+
+/// Fetch bytes `first..=last` into their place in `part`, resuming on failure
+async fn fetch_segment(
+    client: &HttpClient,
+    request: &HttpRequest,
+    part: &Path,
+    first: u64,
+    last: u64,
+    validator: Option<&str>,
+    policy: &RetryPolicy,
+) -> Result<u32> {
+    let mut file = OpenOptions::new().write(true).open(part).await?;
+    let mut position = first;
+    let mut rng = policy.jitter_rng(request.is_deterministic());
+    let mut retries = 0;
+
+    loop {
+        let attempt = async {
+            let mut ranged = request.clone();
+            ranged.set_header("Range", &range_header(position, Some(last)));
+            if let Some(validator) = validator {
+                ranged.set_header("If-Range", validator);
+            }
+
+            let response = client.execute_stream(&ranged).await?;
+            let range = ContentRange::from_head(&response.head)?.and_then(|range| range.range);
+            if response.head.status != 206 || range.map(|(start, _)| start) != Some(position) {
+                return Err(Error::new(ErrorKind::InvalidData, "Resource changed during segmented download"));
+            }
+
+            file.seek(SeekFrom::Start(position)).await?;
+            let mut body = response.body;
+            while let Some(chunk) = body.next_chunk().await {
+                let chunk = chunk?;
+                if position + chunk.len() as u64 > last + 1 {
+                    return Err(Error::new(ErrorKind::InvalidData, "Segment longer than requested"));
+                }
+                file.write_all(&chunk).await?;
+                position += chunk.len() as u64;
+            }
+            if position <= last {
+                return Err(Error::new(ErrorKind::UnexpectedEof, format!("Segment ended at byte {}", position)));
+            }
+            Ok(())
+        };
+
+        match attempt.await {
+            Ok(()) => {
+                file.flush().await?;
+                return Ok(retries);
+            }
+            Err(e) if retries < policy.max_retries() && policy.should_retry_error(request.method(), &e) => {
+                tokio::time::sleep(policy.backoff(retries, &mut rng)).await;
+                retries += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{listen, pooled_client, read_head};
+    use std::io::{BufReader, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(ContentRange::parse("bytes 0-499/1234").unwrap(), ContentRange { range: Some((0, 499)), complete_length: Some(1234) });
+        assert_eq!(ContentRange::parse("bytes 500-999/*").unwrap(), ContentRange { range: Some((500, 999)), complete_length: None });
+        assert_eq!(ContentRange::parse("bytes */1234").unwrap(), ContentRange { range: None, complete_length: Some(1234) });
+        assert!(ContentRange::parse("bytes 5-4/10").is_err());
+        assert!(ContentRange::parse("bytes 0-10/10").is_err());
+        assert!(ContentRange::parse("items 0-1/2").is_err());
+    }
+
+    /// Serves `content` with range support; the very first full response is cut short
+    ///
+    /// `/moved` redirects to `/data.bin`, `/unvalidated` sends no ETag and
+    /// `/broken` fails every range that does not start at byte 0.
+    fn spawn_range_server(content: Vec<u8>) -> (u16, Arc<Mutex<Vec<String>>>) {
+        let (listener, port) = listen();
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let log = heads.clone();
+        let content = Arc::new(content);
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (content, log) = (content.clone(), log.clone());
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    loop {
+                        let head = read_head(&mut reader);
+                        if head.is_empty() {
+                            return;
+                        }
+                        let first_request = {
+                            let mut log = log.lock().unwrap();
+                            log.push(head.clone());
+                            log.len() == 1
+                        };
+
+                        let path = head.split(' ').nth(1).unwrap_or("/").to_string();
+                        if path == "/moved" {
+                            reader.get_mut().write_all(b"HTTP/1.1 302 Found\r\nLocation: /data.bin\r\nContent-Length: 0\r\n\r\n").unwrap();
+                            continue;
+                        }
+                        let etag = if path == "/unvalidated" { "" } else { "ETag: \"v1\"\r\n" };
+
+                        let range = head.lines()
+                            .find_map(|line| line.strip_prefix("Range: bytes="))
+                            .map(|spec| {
+                                let (first, last) = spec.split_once('-').unwrap();
+                                let last = last.parse().unwrap_or(content.len() - 1);
+                                (first.parse::<usize>().unwrap(), last)
+                            });
+                        let writer = reader.get_mut();
+                        match range {
+                            Some((first, _)) if first > 0 && path == "/broken" => {
+                                writer.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n").unwrap();
+                            }
+                            Some((first, last)) => {
+                                let response = format!("HTTP/1.1 206 Partial Content\r\n{}Content-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n", etag, first, last, content.len(), last + 1 - first);
+                                writer.write_all(response.as_bytes()).unwrap();
+                                writer.write_all(&content[first..=last]).unwrap();
+                            }
+                            None => {
+                                let response = format!("HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\n\r\n", etag, content.len());
+                                writer.write_all(response.as_bytes()).unwrap();
+                                if first_request {
+                                    // Drop the connection part way through the body
+                                    writer.write_all(&content[..400]).unwrap();
+                                    return;
+                                }
+                                writer.write_all(&content).unwrap();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        (port, heads)
+    }
+
+    fn options() -> DownloadOptions {
+        let mut policy = RetryPolicy::new();
+        policy.set_base_delay(Duration::from_millis(1));
+        let mut options = DownloadOptions::new();
+        options.set_retry_policy(policy).set_min_segment_size(100);
+        options
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_dropped_connection() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let (port, heads) = spawn_range_server(content.clone());
+        let path = std::env::temp_dir().join(format!("biosurf-download-{}", port));
+
+        let request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/data.bin", port));
+        let report = pooled_client().download(&request, &path, &options()).await.unwrap();
+
+        assert_eq!(report, DownloadReport { size: 1000, resumed_from: 0, segments: 1, retries: 1 });
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        assert!(!with_suffix(&path, ".part.validator").exists());
+        let heads = heads.lock().unwrap();
+        assert!(heads[1].contains("Range: bytes=400-\r\n") && heads[1].contains("If-Range: \"v1\"\r\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_segmented_download_for_throughput() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 241) as u8).collect();
+        let (port, heads) = spawn_range_server(content.clone());
+        let path = std::env::temp_dir().join(format!("biosurf-download-{}", port));
+        // Burn the server's cut-short first response so segments see clean ranges
+        heads.lock().unwrap().push(String::new());
+
+        let mut request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/data.bin", port));
+        request.set_machine_priority(MachineHttpPriority::Throughput);
+        let report = pooled_client().download(&request, &path, &options()).await.unwrap();
+
+        assert_eq!((report.size, report.segments), (1000, 4));
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        let heads = heads.lock().unwrap();
+        assert!(heads.iter().any(|head| head.contains("Range: bytes=750-999\r\n")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_without_validator_starts_over() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 239) as u8).collect();
+        let (port, heads) = spawn_range_server(content.clone());
+        let path = std::env::temp_dir().join(format!("biosurf-download-{}", port));
+
+        let request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/unvalidated", port));
+        let report = pooled_client().download(&request, &path, &options()).await.unwrap();
+
+        assert_eq!(report, DownloadReport { size: 1000, resumed_from: 0, segments: 1, retries: 1 });
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        assert!(!heads.lock().unwrap()[1].contains("Range:"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_follows_redirects() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 233) as u8).collect();
+        let (port, heads) = spawn_range_server(content.clone());
+        heads.lock().unwrap().push(String::new());
+        let url = format!("http://127.0.0.1:{}/moved", port);
+
+        let sequential = std::env::temp_dir().join(format!("biosurf-download-{}-sequential", port));
+        pooled_client().download(&HttpRequest::new("GET", &url), &sequential, &options()).await.unwrap();
+        assert_eq!(std::fs::read(&sequential).unwrap(), content);
+
+        let segmented = std::env::temp_dir().join(format!("biosurf-download-{}-segmented", port));
+        let mut request = HttpRequest::new("GET", &url);
+        request.set_machine_priority(MachineHttpPriority::Throughput);
+        let report = pooled_client().download(&request, &segmented, &options()).await.unwrap();
+        assert_eq!(report.segments, 4);
+        assert_eq!(std::fs::read(&segmented).unwrap(), content);
+
+        // Only the probe went through the redirect; segments used the final URL
+        let heads = heads.lock().unwrap();
+        assert_eq!(heads.iter().filter(|head| head.starts_with("GET /moved ")).count(), 2);
+        std::fs::remove_file(&sequential).unwrap();
+        std::fs::remove_file(&segmented).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_segmented_download_leaves_nothing_to_resume() {
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 229) as u8).collect();
+        let (port, heads) = spawn_range_server(content.clone());
+        heads.lock().unwrap().push(String::new());
+        let path = std::env::temp_dir().join(format!("biosurf-download-{}", port));
+        let url = format!("http://127.0.0.1:{}/broken", port);
+        // Left behind by an earlier, interrupted sequential download
+        std::fs::write(with_suffix(&path, ".part.validator"), "\"v1\"").unwrap();
+
+        let mut request = HttpRequest::new("GET", &url);
+        request.set_machine_priority(MachineHttpPriority::Throughput);
+        assert!(pooled_client().download(&request, &path, &options()).await.is_err());
+        assert!(!with_suffix(&path, ".part").exists());
+        assert!(!with_suffix(&path, ".part.validator").exists());
+
+        // A later sequential call must fetch everything rather than trust the holes
+        let report = pooled_client().download(&HttpRequest::new("GET", &url), &path, &options()).await.unwrap();
+        assert_eq!(report.resumed_from, 0);
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
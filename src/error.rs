@@ -0,0 +1,240 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use crate::body::BodyTooLarge;
+
+/// Why a DNS lookup failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsFailure {
+    /// The name does not exist (NXDOMAIN, rcode 3)
+    NotFound,
+    /// The name exists but has no address records
+    NoRecords,
+    /// The server could not answer (SERVFAIL, rcode 2)
+    ServerFailure,
+    /// Any other error rcode, e.g. 5 for REFUSED
+    Rcode(u8),
+    /// No answer within the resolver's timeout
+    Timeout,
+    /// The answer could not be parsed
+    Malformed,
+}
+
+impl DnsFailure {
+    /// Failure for a non-zero response code
+    pub fn from_rcode(rcode: u8) -> Self {
+        match rcode {
+            2 => DnsFailure::ServerFailure,
+            3 => DnsFailure::NotFound,
+            rcode => DnsFailure::Rcode(rcode),
+        }
+    }
+}
+
+impl fmt::Display for DnsFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsFailure::NotFound => write!(f, "no such domain (NXDOMAIN)"),
+            DnsFailure::NoRecords => write!(f, "no address records"),
+            DnsFailure::ServerFailure => write!(f, "server failure (SERVFAIL)"),
+            DnsFailure::Rcode(rcode) => write!(f, "rcode {}", rcode),
+            DnsFailure::Timeout => write!(f, "timed out"),
+            DnsFailure::Malformed => write!(f, "malformed response"),
+        }
+    }
+}
+
+/// What went wrong with a request, for callers that need to tell failures apart
+///
+/// Carried inside `std::io::Error` so the client keeps returning
+/// `io::Result`; recover it with [`HttpError::find`]. The I/O error kind
+/// matches the variant (e.g. `TimedOut` for [`HttpError::Timeout`]), and
+/// the underlying error stays reachable through `source()`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum HttpError {
+    /// Resolving `host` failed
+    Dns { host: String, failure: DnsFailure, source: Option<Error> },
+    /// The TCP connection, or the proxy tunnel, to `address` could not be opened
+    Connect { address: String, source: Error },
+    /// The proxy at `proxy` rejected our credentials or refused the tunnel by policy
+    Proxy { proxy: String, source: Error },
+    /// The TLS handshake with `host` failed or its certificate was rejected
+    Tls { host: String, source: Box<dyn StdError + Send + Sync> },
+    /// The peer sent something that is not valid HTTP
+    Protocol { message: String, source: Option<Box<dyn StdError + Send + Sync>> },
+    /// A read, write, connect or phase deadline passed
+    Timeout(Box<dyn StdError + Send + Sync>),
+    /// The response body exceeded the configured maximum
+    BodyLimit(BodyTooLarge),
+}
+
+impl HttpError {
+    pub fn dns(host: &str, failure: DnsFailure, source: Option<Error>) -> Error {
+        HttpError::Dns { host: host.to_string(), failure, source }.into()
+    }
+
+    /// A connect failure, unless `source` already says more precisely what failed
+    pub fn connect(address: &str, source: Error) -> Error {
+        if HttpError::find(&source).is_some() {
+            return source;
+        }
+        HttpError::Connect { address: address.to_string(), source }.into()
+    }
+
+    pub fn proxy(proxy: &str, source: Error) -> Error {
+        HttpError::Proxy { proxy: proxy.to_string(), source }.into()
+    }
+
+    pub fn tls(host: &str, source: impl Into<Box<dyn StdError + Send + Sync>>) -> Error {
+        HttpError::Tls { host: host.to_string(), source: source.into() }.into()
+    }
+
+    pub fn protocol(message: impl Into<String>) -> Error {
+        HttpError::Protocol { message: message.into(), source: None }.into()
+    }
+
+    pub fn timeout(cause: impl Into<Box<dyn StdError + Send + Sync>>) -> Error {
+        HttpError::Timeout(cause.into()).into()
+    }
+
+    /// The `HttpError` inside an I/O error, if it carries one
+    pub fn find(error: &Error) -> Option<&HttpError> {
+        find_cause(error)
+    }
+
+    /// The I/O error kind this error is reported with
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            HttpError::Dns { failure, .. } => match failure {
+                DnsFailure::NotFound | DnsFailure::NoRecords => ErrorKind::NotFound,
+                DnsFailure::Timeout => ErrorKind::TimedOut,
+                DnsFailure::Malformed => ErrorKind::InvalidData,
+                DnsFailure::ServerFailure | DnsFailure::Rcode(_) => ErrorKind::Other,
+            },
+            HttpError::Connect { source, .. } | HttpError::Proxy { source, .. } => source.kind(),
+            HttpError::Tls { source, .. } => source.downcast_ref::<Error>().map_or(ErrorKind::Other, Error::kind),
+            HttpError::Protocol { .. } if self.is_refused_stream() => ErrorKind::ConnectionAborted,
+            HttpError::Protocol { .. } => ErrorKind::InvalidData,
+            HttpError::Timeout(_) => ErrorKind::TimedOut,
+            HttpError::BodyLimit(_) => ErrorKind::FileTooLarge,
+        }
+    }
+
+    /// Whether trying again later might succeed
+    ///
+    /// True for timeouts, connect failures, DNS server trouble and refused
+    /// HTTP/2 streams; false for names that do not exist, proxy refusals, TLS
+    /// and other protocol errors and oversized bodies.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpError::Dns { failure, .. } => matches!(failure, DnsFailure::ServerFailure | DnsFailure::Timeout),
+            HttpError::Connect { .. } | HttpError::Timeout(_) => true,
+            HttpError::Protocol { .. } => self.is_refused_stream(),
+            HttpError::Proxy { .. } | HttpError::Tls { .. } | HttpError::BodyLimit(_) => false,
+        }
+    }
+
+    /// Whether an HTTP/2 peer turned the request away unprocessed (GOAWAY or REFUSED_STREAM)
+    ///
+    /// Reported with `ErrorKind::ConnectionAborted`, since the request can be
+    /// sent again on a new connection.
+    pub fn is_refused_stream(&self) -> bool {
+        let HttpError::Protocol { source: Some(source), .. } = self else {
+            return false;
+        };
+        source.downcast_ref::<h2::Error>()
+            .is_some_and(|error| error.is_go_away() || error.reason() == Some(h2::Reason::REFUSED_STREAM))
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Dns { host, failure, source: None } => write!(f, "DNS lookup for {} failed: {}", host, failure),
+            HttpError::Dns { host, failure, source: Some(source) } => write!(f, "DNS lookup for {} failed: {} ({})", host, failure, source),
+            HttpError::Connect { address, source } => write!(f, "Connecting to {} failed: {}", address, source),
+            HttpError::Proxy { proxy, source } => write!(f, "Proxy {} refused the tunnel: {}", proxy, source),
+            HttpError::Tls { host, source } => write!(f, "TLS handshake with {} failed: {}", host, source),
+            HttpError::Protocol { message, .. } => write!(f, "{}", message),
+            HttpError::Timeout(cause) => write!(f, "{}", cause),
+            HttpError::BodyLimit(limit) => write!(f, "{}", limit),
+        }
+    }
+}
+
+impl StdError for HttpError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            HttpError::Dns { source, .. } => source.as_ref().map(|source| source as _),
+            HttpError::Connect { source, .. } | HttpError::Proxy { source, .. } => Some(source),
+            HttpError::Tls { source, .. } => Some(source.as_ref()),
+            HttpError::Protocol { source, .. } => source.as_deref().map(|source| source as _),
+            HttpError::Timeout(cause) => Some(cause.as_ref()),
+            HttpError::BodyLimit(limit) => Some(limit),
+        }
+    }
+}
+
+impl From<HttpError> for Error {
+    fn from(error: HttpError) -> Self {
+        Error::new(error.kind(), error)
+    }
+}
+
+/// The first `T` in the chain of errors `error` wraps
+pub fn find_cause<T: StdError + 'static>(error: &Error) -> Option<&T> {
+    let mut current: Option<&(dyn StdError + 'static)> = error.get_ref().map(|inner| inner as _);
+    while let Some(inner) = current {
+        if let Some(found) = inner.downcast_ref::<T>() {
+            return Some(found);
+        }
+        current = inner.source();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_source_and_find() {
+        let refused = Error::new(ErrorKind::ConnectionRefused, "refused");
+        let error = HttpError::connect("127.0.0.1:9", refused);
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        assert_eq!(error.to_string(), "Connecting to 127.0.0.1:9 failed: refused");
+        assert!(HttpError::find(&error).unwrap().is_transient());
+        assert_eq!(error.get_ref().unwrap().source().unwrap().to_string(), "refused");
+
+        // A more specific error is passed through rather than wrapped
+        let timeout = HttpError::connect("127.0.0.1:9", HttpError::timeout("Connect timed out"));
+        assert!(matches!(HttpError::find(&timeout), Some(HttpError::Timeout(_))));
+
+        let nxdomain = HttpError::dns("missing.example", DnsFailure::from_rcode(3), None);
+        assert_eq!(nxdomain.kind(), ErrorKind::NotFound);
+        assert!(matches!(HttpError::find(&nxdomain), Some(HttpError::Dns { failure: DnsFailure::NotFound, .. })));
+        assert!(!HttpError::find(&nxdomain).unwrap().is_transient());
+        assert!(HttpError::find(&HttpError::dns("flaky.example", DnsFailure::from_rcode(2), None)).unwrap().is_transient());
+        assert!(HttpError::find(&Error::other("plain")).is_none());
+
+        // Proxy refusals keep their own variant through the connect wrapper
+        let denied = HttpError::proxy("proxy:3128", Error::new(ErrorKind::PermissionDenied, "Proxy authentication required"));
+        let error = HttpError::connect("10.0.0.1:3128", denied);
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        assert!(matches!(HttpError::find(&error), Some(HttpError::Proxy { .. })));
+        assert!(!HttpError::find(&error).unwrap().is_transient());
+    }
+
+    #[test]
+    fn test_refused_http2_streams_stay_retryable() {
+        let refused = HttpError::Protocol { message: "refused".to_string(), source: Some(h2::Error::from(h2::Reason::REFUSED_STREAM).into()) };
+        assert!(refused.is_refused_stream() && refused.is_transient());
+        assert_eq!(Error::from(refused).kind(), ErrorKind::ConnectionAborted);
+
+        let broken = HttpError::Protocol { message: "broken".to_string(), source: Some(h2::Error::from(h2::Reason::PROTOCOL_ERROR).into()) };
+        assert!(!broken.is_refused_stream() && !broken.is_transient());
+        assert_eq!(Error::from(broken).kind(), ErrorKind::InvalidData);
+    }
+}
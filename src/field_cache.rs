@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::cache::{header_value, CacheStatus};
+use crate::headers::HeaderMap;
+use crate::http_client::{FieldCacheDirective, HttpResponse};
+use crate::machine_http::MachineHttpMeta;
+
+/// One step of a concrete location inside a JSON document
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Wildcard,
+}
+
+/// A parsed JSONPath expression
+///
+/// Supports the subset used by field cache directives: `$`, `.name`,
+/// `['name']`, `[index]` (negative counts from the end), `*` / `[*]` and
+/// recursive descent with `..`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    /// Selectors in order, each flagged if it applies to all descendants
+    segments: Vec<(bool, Selector)>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::new(ErrorKind::InvalidInput, format!("Invalid JSONPath {}: {}", path, reason));
+        let mut rest = path.trim().strip_prefix('$').ok_or_else(|| invalid("must start with $"))?;
+        let mut segments = Vec::new();
+
+        while !rest.is_empty() {
+            let recursive = rest.starts_with("..");
+            if recursive {
+                rest = &rest[2..];
+            } else if let Some(after_dot) = rest.strip_prefix('.') {
+                rest = after_dot;
+            } else if !rest.starts_with('[') {
+                return Err(invalid("expected . or ["));
+            }
+
+            if let Some(bracket) = rest.strip_prefix('[') {
+                let end = bracket.find(']').ok_or_else(|| invalid("unclosed ["))?;
+                let inner = bracket[..end].trim();
+                let selector = if inner == "*" {
+                    Selector::Wildcard
+                } else if let Some(name) = inner.strip_prefix('\'').and_then(|inner| inner.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|inner| inner.strip_suffix('"')))
+                {
+                    Selector::Name(name.to_string())
+                } else {
+                    Selector::Index(inner.parse().map_err(|_| invalid("expected an index, a quoted name or *"))?)
+                };
+                segments.push((recursive, selector));
+                rest = &bracket[end + 1..];
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                let selector = match &rest[..end] {
+                    "" => return Err(invalid("empty name")),
+                    "*" => Selector::Wildcard,
+                    name => Selector::Name(name.to_string()),
+                };
+                segments.push((recursive, selector));
+                rest = &rest[end..];
+            }
+        }
+
+        Ok(JsonPath { segments })
+    }
+
+    /// Every value the path selects, with its concrete location
+    pub fn select<'v>(&self, document: &'v Value) -> Vec<(Vec<PathStep>, &'v Value)> {
+        let mut current = vec![(Vec::new(), document)];
+
+        for (recursive, selector) in &self.segments {
+            let mut next = Vec::new();
+            for (location, node) in current {
+                if *recursive {
+                    let mut stack = vec![(location, node)];
+                    while let Some((location, node)) = stack.pop() {
+                        apply(selector, &location, node, &mut next);
+                        // Push children in reverse so descendants are visited in document order
+                        let mut children = Vec::new();
+                        apply(&Selector::Wildcard, &location, node, &mut children);
+                        stack.extend(children.into_iter().rev());
+                    }
+                } else {
+                    apply(selector, &location, node, &mut next);
+                }
+            }
+            current = next;
+        }
+
+        current
+    }
+}
+
+fn apply<'v>(selector: &Selector, location: &[PathStep], node: &'v Value, out: &mut Vec<(Vec<PathStep>, &'v Value)>) {
+    let child = |step: PathStep| {
+        let mut location = location.to_vec();
+        location.push(step);
+        location
+    };
+    match (selector, node) {
+        (Selector::Name(name), Value::Object(map)) => {
+            if let Some(value) = map.get(name) {
+                out.push((child(PathStep::Key(name.clone())), value));
+            }
+        }
+        (Selector::Index(index), Value::Array(items)) => {
+            let position = if *index < 0 { items.len() as i64 + index } else { *index };
+            if let Some(value) = usize::try_from(position).ok().and_then(|position| items.get(position)) {
+                out.push((child(PathStep::Index(position as usize)), value));
+            }
+        }
+        (Selector::Wildcard, Value::Object(map)) => {
+            out.extend(map.iter().map(|(key, value)| (child(PathStep::Key(key.clone())), value)));
+        }
+        (Selector::Wildcard, Value::Array(items)) => {
+            out.extend(items.iter().enumerate().map(|(position, value)| (child(PathStep::Index(position)), value)));
+        }
+        _ => {}
+    }
+}
+
+/// Insert `value` at `location` if its parent exists and the slot is empty
+fn insert_missing(document: &mut Value, location: &[PathStep], value: Value) -> bool {
+    let Some((last, parents)) = location.split_last() else {
+        return false;
+    };
+    let mut node = document;
+    for step in parents {
+        node = match (step, node) {
+            (PathStep::Key(key), Value::Object(map)) => match map.get_mut(key) {
+                Some(child) => child,
+                None => return false,
+            },
+            (PathStep::Index(position), Value::Array(items)) => match items.get_mut(*position) {
+                Some(child) => child,
+                None => return false,
+            },
+            _ => return false,
+        };
+    }
+    match (last, node) {
+        (PathStep::Key(key), Value::Object(map)) if !map.contains_key(key) => {
+            map.insert(key.clone(), value);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// How usable the cached values of a set of directives are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Freshness {
+    Fresh,
+    /// Past the TTL but within stale-while-revalidate
+    Stale,
+    Expired,
+}
+
+#[derive(Debug)]
+struct CachedField {
+    values: Vec<(Vec<PathStep>, Value)>,
+    stored: Instant,
+}
+
+impl CachedField {
+    fn freshness(&self, directive: &FieldCacheDirective, now: Instant) -> Freshness {
+        let age = now.duration_since(self.stored);
+        let ttl = Duration::from_secs(directive.ttl().into());
+        let stale_window = Duration::from_secs(directive.stale_while_revalidate().unwrap_or(0).into());
+        if age < ttl {
+            Freshness::Fresh
+        } else if age < ttl + stale_window {
+            Freshness::Stale
+        } else {
+            Freshness::Expired
+        }
+    }
+}
+
+/// Request headers that select a cached document besides those named by Vary
+const SELECTING_HEADERS: [&str; 2] = ["authorization", "cookie"];
+
+/// The latest response for a URL and the field values selected from it
+#[derive(Debug)]
+struct CachedDocument {
+    /// Lowercased selecting header names and the request's values for them
+    selected_by: Vec<(String, Option<String>)>,
+    version: String,
+    status: u16,
+    status_text: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    fields: HashMap<String, CachedField>,
+    revalidating: bool,
+}
+
+impl CachedDocument {
+    fn matches(&self, request_headers: &HeaderMap) -> bool {
+        self.selected_by.iter().all(|(name, value)| header_value(request_headers, name) == *value)
+    }
+
+    fn to_response(&self, cache_status: CacheStatus) -> HttpResponse {
+        HttpResponse {
+            version: self.version.clone(),
+            status: self.status,
+            status_text: self.status_text.clone(),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            encoded_size: self.body.len(),
+            redirects: Vec::new(),
+            timings: Default::default(),
+            peer_leaf_certificate: None,
+            cache_status,
+            machine: MachineHttpMeta::from_headers(&self.headers),
+        }
+    }
+}
+
+/// Result of looking a request's field directives up in the cache
+#[derive(Debug)]
+pub enum FieldLookup {
+    /// Every directed field is within its TTL
+    Fresh(HttpResponse),
+    /// Some field is in its stale-while-revalidate window; `revalidate` is
+    /// set for the one caller that should refresh it in the background
+    Stale { response: HttpResponse, revalidate: bool },
+    Miss,
+}
+
+/// Client-side enforcement of `X-Machine-Field-Cache` directives
+///
+/// Values selected by each directive's JSONPath are cached per URL with the
+/// directive's TTL. While all directed fields are fresh, GET requests are
+/// answered from the last response; in the stale-while-revalidate window
+/// they are answered with `CacheStatus::Stale` while one background request
+/// refreshes the entry. Fields a server omits from a later response are
+/// filled in from the cache, by position, while still usable. Clones share
+/// entries.
+///
+/// Like the response cache, each URL holds variants selected by the
+/// request's Authorization and Cookie headers and by the response's Vary,
+/// so one user's fields are never served to another.
+#[derive(Debug, Clone, Default)]
+pub struct FieldCache {
+    documents: Arc<Mutex<HashMap<String, Vec<CachedDocument>>>>,
+}
+
+impl FieldCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up `url`, judging freshness by the directives of the new request
+    pub fn lookup(&self, url: &str, request_headers: &HeaderMap, directives: &[FieldCacheDirective]) -> FieldLookup {
+        let mut documents = self.documents.lock().unwrap();
+        let Some(document) = documents.get_mut(url)
+            .and_then(|variants| variants.iter_mut().find(|document| document.matches(request_headers)))
+        else {
+            return FieldLookup::Miss;
+        };
+
+        let now = Instant::now();
+        let freshness = directives.iter()
+            .map(|directive| match document.fields.get(directive.field_path()) {
+                Some(field) => field.freshness(directive, now),
+                None => Freshness::Expired,
+            })
+            .max()
+            .unwrap_or(Freshness::Expired);
+
+        match freshness {
+            Freshness::Fresh => FieldLookup::Fresh(document.to_response(CacheStatus::Hit)),
+            Freshness::Stale => {
+                let revalidate = !std::mem::replace(&mut document.revalidating, true);
+                FieldLookup::Stale { response: document.to_response(CacheStatus::Stale), revalidate }
+            }
+            Freshness::Expired => FieldLookup::Miss,
+        }
+    }
+
+    /// Merge usable cached fields into a fresh JSON response and cache its fields
+    ///
+    /// Responses that are not successful JSON, or that vary on `*`, are
+    /// passed through untouched.
+    pub fn update(&self, url: &str, request_headers: &HeaderMap, directives: &[FieldCacheDirective], response: &mut HttpResponse) {
+        if !(200..300).contains(&response.status) {
+            return;
+        }
+        let mut selecting: Vec<String> = SELECTING_HEADERS.iter().map(|name| name.to_string()).collect();
+        for name in response.headers.get_all("vary").flat_map(|value| value.split(',')) {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "*" {
+                return;
+            }
+            if !name.is_empty() && !selecting.contains(&name) {
+                selecting.push(name);
+            }
+        }
+        let Ok(mut document) = response.json() else {
+            return;
+        };
+
+        let mut documents = self.documents.lock().unwrap();
+        let variants = documents.entry(url.to_string()).or_default();
+        let previous = variants.iter().position(|document| document.matches(request_headers))
+            .map(|position| variants.remove(position));
+        let mut previous_fields = previous.map(|previous| previous.fields).unwrap_or_default();
+        let now = Instant::now();
+        let mut fields = HashMap::new();
+        let mut merged = false;
+
+        for directive in directives {
+            let Ok(path) = JsonPath::parse(directive.field_path()) else {
+                continue;
+            };
+            let selected: Vec<_> = path.select(&document).into_iter()
+                .map(|(location, value)| (location, value.clone()))
+                .collect();
+            let cached = previous_fields.remove(directive.field_path())
+                .filter(|field| field.freshness(directive, now) != Freshness::Expired);
+
+            match cached {
+                // The server left the field out: keep serving the cached values
+                Some(field) if selected.is_empty() => {
+                    for (location, value) in &field.values {
+                        merged |= insert_missing(&mut document, location, value.clone());
+                    }
+                    fields.insert(directive.field_path().to_string(), field);
+                }
+                _ if !selected.is_empty() => {
+                    fields.insert(directive.field_path().to_string(), CachedField { values: selected, stored: now });
+                }
+                _ => {}
+            }
+        }
+
+        // Fields this request did not direct keep their own age
+        for (field_path, field) in previous_fields {
+            fields.entry(field_path).or_insert(field);
+        }
+
+        if merged && let Ok(body) = serde_json::to_vec(&document) {
+            if response.headers.contains("content-length") {
+                response.headers.insert("Content-Length", &body.len().to_string());
+            }
+            response.body = body;
+        }
+        if !fields.is_empty() {
+            let selected_by = selecting.into_iter()
+                .map(|name| {
+                    let value = header_value(request_headers, &name);
+                    (name, value)
+                })
+                .collect();
+            variants.push(CachedDocument {
+                selected_by,
+                version: response.version.clone(),
+                status: response.status,
+                status_text: response.status_text.clone(),
+                headers: response.headers.clone(),
+                body: response.body.clone(),
+                fields,
+                revalidating: false,
+            });
+        }
+        if variants.is_empty() {
+            documents.remove(url);
+        }
+    }
+
+    /// Allow another background revalidation of `url`, e.g. after one failed
+    pub fn end_revalidation(&self, url: &str, request_headers: &HeaderMap) {
+        if let Some(document) = self.documents.lock().unwrap().get_mut(url)
+            .and_then(|variants| variants.iter_mut().find(|document| document.matches(request_headers)))
+        {
+            document.revalidating = false;
+        }
+    }
+
+    /// Forget everything cached for `url`
+    pub fn invalidate(&self, url: &str) {
+        self.documents.lock().unwrap().remove(url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_select_json_path() {
+        let document = json!({"data": {"items": [{"price": 1, "tags": ["a"]}, {"price": 2}], "total": {"price": 3}}});
+        let prices = |path: &str| -> Vec<Value> {
+            JsonPath::parse(path).unwrap().select(&document).into_iter().map(|(_, value)| value.clone()).collect()
+        };
+
+        assert_eq!(prices("$.data.items[*].price"), [json!(1), json!(2)]);
+        assert_eq!(prices("$['data'].items[-1].price"), [json!(2)]);
+        assert_eq!(prices("$..price"), [json!(1), json!(2), json!(3)]);
+        assert_eq!(JsonPath::parse("$.data.items[1]").unwrap().select(&document)[0].0,
+            [PathStep::Key("data".to_string()), PathStep::Key("items".to_string()), PathStep::Index(1)]);
+        assert!(JsonPath::parse("data.items").is_err());
+        assert!(JsonPath::parse("$.items[x]").is_err());
+    }
+
+    #[test]
+    fn test_merge_omitted_fields_and_freshness() {
+        let response = |body: &str| {
+            HttpResponse::parse(format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()).unwrap()
+        };
+        let mut request = crate::http_client::HttpRequest::new("GET", "http://example.com/items");
+        request.add_field_cache_directive("$.items[*].price", 60, Some(30));
+        let directives = request.field_cache_directives();
+        let cache = FieldCache::new();
+
+        assert!(matches!(cache.lookup("http://example.com/items", request.headers(), directives), FieldLookup::Miss));
+        cache.update("http://example.com/items", request.headers(), directives, &mut response(r#"{"items":[{"id":1,"price":5}]}"#));
+        let FieldLookup::Fresh(hit) = cache.lookup("http://example.com/items", request.headers(), directives) else {
+            panic!("expected a fresh hit");
+        };
+        assert_eq!(hit.json().unwrap(), json!({"items": [{"id": 1, "price": 5}]}));
+
+        // A response without the field gets the cached price merged in
+        let mut partial = response(r#"{"items":[{"id":1}]}"#);
+        cache.update("http://example.com/items", request.headers(), directives, &mut partial);
+        assert_eq!(partial.json().unwrap(), json!({"items": [{"id": 1, "price": 5}]}));
+        assert_eq!(partial.header("content-length"), Some(partial.body.len().to_string().as_str()));
+
+        // A stricter TTL on a later request puts the field in its stale window
+        let mut strict = crate::http_client::HttpRequest::new("GET", "http://example.com/items");
+        strict.add_field_cache_directive("$.items[*].price", 0, Some(30));
+        let lookup = cache.lookup("http://example.com/items", strict.headers(), strict.field_cache_directives());
+        assert!(matches!(lookup, FieldLookup::Stale { revalidate: true, .. }));
+        let lookup = cache.lookup("http://example.com/items", strict.headers(), strict.field_cache_directives());
+        assert!(matches!(lookup, FieldLookup::Stale { revalidate: false, .. }));
+    }
+
+    #[test]
+    fn test_variants_selected_by_credentials_and_vary() {
+        let response = |vary: &str, body: &str| {
+            HttpResponse::parse(format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\n\r\n{}", vary, body.len(), body).as_bytes()).unwrap()
+        };
+        let url = "http://example.com/account";
+        let mut alice = crate::http_client::HttpRequest::new("GET", url);
+        alice.add_field_cache_directive("$.balance", 60, None);
+        alice.set_header("Authorization", "Bearer alice");
+        let mut bob = alice.clone();
+        bob.set_header("Authorization", "Bearer bob");
+        let anonymous = crate::http_client::HttpRequest::new("GET", url);
+        let directives = alice.field_cache_directives();
+        let cache = FieldCache::new();
+
+        cache.update(url, alice.headers(), directives, &mut response("", r#"{"balance":1}"#));
+        assert!(matches!(cache.lookup(url, alice.headers(), directives), FieldLookup::Fresh(_)));
+        assert!(matches!(cache.lookup(url, bob.headers(), directives), FieldLookup::Miss));
+        assert!(matches!(cache.lookup(url, anonymous.headers(), directives), FieldLookup::Miss));
+
+        // Bob's own response is a second variant rather than a replacement
+        cache.update(url, bob.headers(), directives, &mut response("", r#"{"balance":2}"#));
+        let FieldLookup::Fresh(hit) = cache.lookup(url, alice.headers(), directives) else {
+            panic!("expected a fresh hit");
+        };
+        assert_eq!(hit.json().unwrap(), json!({"balance": 1}));
+
+        let mut german = alice.clone();
+        german.set_header("Accept-Language", "de");
+        cache.update(url, german.headers(), directives, &mut response("Vary: Accept-Language\r\n", r#"{"balance":3}"#));
+        assert!(matches!(cache.lookup(url, german.headers(), directives), FieldLookup::Fresh(_)));
+        assert!(matches!(cache.lookup(url, alice.headers(), directives), FieldLookup::Miss));
+
+        cache.update(url, alice.headers(), directives, &mut response("Vary: *\r\n", r#"{"balance":4}"#));
+        assert!(matches!(cache.lookup(url, alice.headers(), directives), FieldLookup::Miss));
+    }
+}
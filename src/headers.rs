@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+/// Ordered, case-insensitive, multi-value header collection
+///
+/// Names keep the case they were added with and entries keep insertion
+/// order, so requests go out exactly as built. Lookups go through an index
+/// keyed by the lowercased name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+    index: HashMap<String, Vec<usize>>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse header lines, joining obs-fold continuation lines (RFC 9112 §5.2)
+    pub fn parse_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut headers = HeaderMap::new();
+
+        for line in lines {
+            if line.starts_with([' ', '\t']) {
+                // A continuation replaces the line break with a single space
+                if let Some((_, value)) = headers.entries.last_mut() {
+                    let continuation = line.trim();
+                    if !continuation.is_empty() {
+                        if !value.is_empty() {
+                            value.push(' ');
+                        }
+                        value.push_str(continuation);
+                    }
+                }
+                continue;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.append(name.trim(), value.trim());
+            }
+        }
+
+        headers
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a value, keeping any existing values for the name
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.index.entry(name.to_ascii_lowercase()).or_default().push(self.entries.len());
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Set a single value, replacing existing ones in place of the first
+    pub fn insert(&mut self, name: &str, value: &str) {
+        match self.index.get(&name.to_ascii_lowercase()).and_then(|positions| positions.first().copied()) {
+            Some(first) => {
+                self.entries[first] = (name.to_string(), value.to_string());
+                let mut seen = false;
+                self.retain(|n, _| {
+                    let duplicate = n.eq_ignore_ascii_case(name) && seen;
+                    seen |= n.eq_ignore_ascii_case(name);
+                    !duplicate
+                });
+            }
+            None => self.append(name, value),
+        }
+    }
+
+    /// First value for a name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).next()
+    }
+
+    /// Every value for a name, in order (e.g. each Set-Cookie)
+    pub fn get_all<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> + 'a {
+        self.index.get(&name.to_ascii_lowercase())
+            .into_iter()
+            .flatten()
+            .map(|&position| self.entries[position].1.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(&name.to_ascii_lowercase())
+    }
+
+    /// Remove every value for a name, returning whether any existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.entries.len();
+        self.retain(|n, _| !n.eq_ignore_ascii_case(name));
+        self.entries.len() != before
+    }
+
+    /// Keep only the entries for which `keep` returns true
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &str) -> bool) {
+        self.entries.retain(|(name, value)| keep(name, value));
+        self.reindex();
+    }
+
+    /// Entries in insertion order, with names as added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (position, (name, _)) in self.entries.iter().enumerate() {
+            self.index.entry(name.to_ascii_lowercase()).or_default().push(position);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a str)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_insensitive_multi_value() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Content-Type", "text/html");
+        headers.append("set-cookie", "b=2");
+
+        assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert_eq!(headers.iter().map(|(name, _)| name).collect::<Vec<_>>(), ["Set-Cookie", "Content-Type", "set-cookie"]);
+
+        headers.insert("SET-COOKIE", "c=3");
+        assert_eq!(headers.get_all("set-cookie").collect::<Vec<_>>(), ["c=3"]);
+        assert_eq!(headers.iter().next(), Some(("SET-COOKIE", "c=3")));
+
+        assert!(headers.remove("content-type"));
+        assert!(!headers.contains("Content-Type"));
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_obs_fold() {
+        let headers = HeaderMap::parse_lines("X-Long: first\r\n  second\r\n\tthird\r\nHost: example.com".lines());
+
+        assert_eq!(headers.get("x-long"), Some("first second third"));
+        assert_eq!(headers.get("host"), Some("example.com"));
+        assert_eq!(headers.len(), 2);
+    }
+}
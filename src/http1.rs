@@ -0,0 +1,554 @@
+use std::io::{BufRead, Error, ErrorKind, Result};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::error::HttpError;
+use crate::headers::HeaderMap;
+
+/// Upper bound for a response head (status line plus headers)
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+/// Upper bound for a single chunk-size or trailer line
+const MAX_LINE_SIZE: usize = 8 * 1024;
+
+/// Parsed status line and headers of an HTTP/1.x response
+#[derive(Debug, Clone)]
+pub struct ResponseHead {
+    pub version: String,
+    pub status: u16,
+    pub status_text: String,
+    /// Values are trimmed and obs-folded lines joined
+    pub headers: HeaderMap,
+}
+
+impl ResponseHead {
+    /// Parse a response head (everything up to, but excluding, the blank line)
+    pub fn parse(head: &[u8]) -> Result<Self> {
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+
+        let status_line = lines.next().ok_or_else(|| HttpError::protocol("Empty response"))?;
+        let mut parts = status_line.split_whitespace();
+
+        let version = parts.next().ok_or_else(|| HttpError::protocol("Invalid status line"))?;
+        if !version.starts_with("HTTP/") {
+            return Err(HttpError::protocol("Invalid status line"));
+        }
+        let status = parts.next().ok_or_else(|| HttpError::protocol("Missing status code"))?
+            .parse::<u16>().map_err(|_| HttpError::protocol("Invalid status code"))?;
+        let status_text = parts.collect::<Vec<&str>>().join(" ");
+
+        let headers = HeaderMap::parse_lines(lines);
+
+        Ok(ResponseHead {
+            version: version.to_string(),
+            status,
+            status_text,
+            headers,
+        })
+    }
+
+    /// First value of a header (name is matched case-insensitively)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Whether the connection can carry another request after this response
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(&self.version, self.header("connection"))
+    }
+
+    /// Determine how the body is delimited (RFC 9112 section 6.3)
+    pub fn body_framing(&self, request_method: &str) -> Result<BodyFraming> {
+        // Responses to HEAD, and 1xx/204/304 responses, never carry a body
+        if request_method.eq_ignore_ascii_case("HEAD")
+            || (100..200).contains(&self.status)
+            || self.status == 204
+            || self.status == 304
+        {
+            return Ok(BodyFraming::Empty);
+        }
+
+        // A successful CONNECT turns the connection into a tunnel
+        if request_method.eq_ignore_ascii_case("CONNECT") && (200..300).contains(&self.status) {
+            return Ok(BodyFraming::Empty);
+        }
+
+        let transfer_encodings: Vec<String> = self.headers.get_all("transfer-encoding")
+            .flat_map(|v| v.split(','))
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        // Transfer-Encoding overrides Content-Length; chunked must be the final coding
+        if !transfer_encodings.is_empty() {
+            return Ok(if transfer_encodings.last().map(String::as_str) == Some("chunked") {
+                BodyFraming::Chunked
+            } else {
+                BodyFraming::CloseDelimited
+            });
+        }
+
+        let mut content_length: Option<u64> = None;
+        for value in self.headers.get_all("content-length") {
+            for part in value.split(',') {
+                let length = part.trim().parse::<u64>()
+                    .map_err(|_| HttpError::protocol(format!("Invalid Content-Length: {}", value)))?;
+                if content_length.is_some_and(|existing| existing != length) {
+                    return Err(HttpError::protocol("Conflicting Content-Length headers"));
+                }
+                content_length = Some(length);
+            }
+        }
+
+        Ok(match content_length {
+            Some(0) => BodyFraming::Empty,
+            Some(length) => BodyFraming::ContentLength(length),
+            None => BodyFraming::CloseDelimited,
+        })
+    }
+}
+
+/// Connection persistence rules: HTTP/1.0 opts in, HTTP/1.1 opts out
+pub fn keep_alive(version: &str, connection: Option<&str>) -> bool {
+    let connection = connection.unwrap_or("").to_ascii_lowercase();
+    let has_token = |token: &str| connection.split(',').any(|t| t.trim() == token);
+
+    if version == "HTTP/1.0" {
+        has_token("keep-alive")
+    } else {
+        !has_token("close")
+    }
+}
+
+/// How the end of a response body is determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    /// No body follows the head
+    Empty,
+    /// Exactly this many bytes follow the head
+    ContentLength(u64),
+    /// Body uses chunked transfer coding
+    Chunked,
+    /// Body runs until the server closes the connection
+    CloseDelimited,
+}
+
+/// A piece of a response produced by [`ResponseDecoder::decode`]
+#[derive(Debug)]
+pub enum Frame<'a> {
+    /// The final (non-1xx) response head
+    Head(ResponseHead),
+    /// A run of decoded body bytes
+    Data(&'a [u8]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    Head,
+    Length(u64),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkDataEnd,
+    Trailers,
+    UntilClose,
+    Done,
+}
+
+/// Incremental decoder for a single HTTP/1.1 response
+///
+/// The decoder does no I/O: callers hand it whatever bytes they have and it
+/// reports how many it consumed, so bytes belonging to the next message stay
+/// with the caller.
+pub struct ResponseDecoder {
+    method: String,
+    state: DecoderState,
+    /// Partially received head, chunk-size line or trailer line
+    buffer: Vec<u8>,
+}
+
+impl ResponseDecoder {
+    /// Create a decoder for the response to a request with the given method
+    pub fn new(method: &str) -> Self {
+        ResponseDecoder {
+            method: method.to_string(),
+            state: DecoderState::Head,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Whether the complete response has been decoded
+    pub fn is_complete(&self) -> bool {
+        self.state == DecoderState::Done
+    }
+
+    /// Decode from the front of `input`
+    ///
+    /// Returns the number of bytes consumed and at most one frame. A result
+    /// of `(0, None)` means the input was empty or the response is complete.
+    pub fn decode<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Option<Frame<'a>>)> {
+        match self.state {
+            DecoderState::Head => {
+                let (consumed, found) = self.take_until(input, b"\r\n\r\n", MAX_HEAD_SIZE)?;
+                if !found {
+                    return Ok((consumed, None));
+                }
+
+                let head = ResponseHead::parse(&self.buffer[..self.buffer.len() - 4])?;
+                self.buffer.clear();
+
+                // Interim responses (100 Continue, 103 Early Hints) are skipped
+                if (100..200).contains(&head.status) && head.status != 101 {
+                    return Ok((consumed, None));
+                }
+
+                self.state = match head.body_framing(&self.method)? {
+                    BodyFraming::Empty => DecoderState::Done,
+                    BodyFraming::ContentLength(length) => DecoderState::Length(length),
+                    BodyFraming::Chunked => DecoderState::ChunkSize,
+                    BodyFraming::CloseDelimited => DecoderState::UntilClose,
+                };
+                Ok((consumed, Some(Frame::Head(head))))
+            }
+            DecoderState::Length(remaining) => {
+                let take = remaining.min(input.len() as u64) as usize;
+                let remaining = remaining - take as u64;
+                self.state = if remaining == 0 { DecoderState::Done } else { DecoderState::Length(remaining) };
+                Ok((take, Self::data(&input[..take])))
+            }
+            DecoderState::ChunkSize => {
+                let (consumed, found) = self.take_until(input, b"\r\n", MAX_LINE_SIZE)?;
+                if !found {
+                    return Ok((consumed, None));
+                }
+
+                // Parse chunk size (in hex), ignoring any chunk extensions
+                let size_line = String::from_utf8_lossy(&self.buffer[..self.buffer.len() - 2]).into_owned();
+                self.buffer.clear();
+                let chunk_size_str = size_line.split(';').next().unwrap().trim();
+                let chunk_size = u64::from_str_radix(chunk_size_str, 16)
+                    .map_err(|e| HttpError::Protocol { message: format!("Invalid chunk size: {}", e), source: Some(e.into()) })?;
+
+                self.state = if chunk_size == 0 { DecoderState::Trailers } else { DecoderState::ChunkData(chunk_size) };
+                Ok((consumed, None))
+            }
+            DecoderState::ChunkData(remaining) => {
+                let take = remaining.min(input.len() as u64) as usize;
+                let remaining = remaining - take as u64;
+                self.state = if remaining == 0 { DecoderState::ChunkDataEnd } else { DecoderState::ChunkData(remaining) };
+                Ok((take, Self::data(&input[..take])))
+            }
+            DecoderState::ChunkDataEnd => {
+                let (consumed, found) = self.take_until(input, b"\r\n", 2)?;
+                if found {
+                    if self.buffer != b"\r\n" {
+                        return Err(HttpError::protocol("Missing CRLF after chunk data"));
+                    }
+                    self.buffer.clear();
+                    self.state = DecoderState::ChunkSize;
+                }
+                Ok((consumed, None))
+            }
+            DecoderState::Trailers => {
+                let (consumed, found) = self.take_until(input, b"\r\n", MAX_LINE_SIZE)?;
+                if found {
+                    // Trailer fields are discarded; an empty line ends the message
+                    if self.buffer == b"\r\n" {
+                        self.state = DecoderState::Done;
+                    }
+                    self.buffer.clear();
+                }
+                Ok((consumed, None))
+            }
+            DecoderState::UntilClose => Ok((input.len(), Self::data(input))),
+            DecoderState::Done => Ok((0, None)),
+        }
+    }
+
+    /// Signal that the peer closed the connection
+    pub fn decode_eof(&mut self) -> Result<()> {
+        match self.state {
+            DecoderState::UntilClose | DecoderState::Done => {
+                self.state = DecoderState::Done;
+                Ok(())
+            }
+            DecoderState::Head if self.buffer.is_empty() => {
+                Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before response"))
+            }
+            DecoderState::Head => Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed mid-headers")),
+            _ => Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed mid-body")),
+        }
+    }
+
+    fn data(bytes: &[u8]) -> Option<Frame<'_>> {
+        if bytes.is_empty() { None } else { Some(Frame::Data(bytes)) }
+    }
+
+    /// Append input to the internal buffer up to and including `delim`
+    ///
+    /// Returns the bytes consumed and whether the delimiter was reached.
+    fn take_until(&mut self, input: &[u8], delim: &[u8], limit: usize) -> Result<(usize, bool)> {
+        let old_len = self.buffer.len();
+        let search_from = old_len.saturating_sub(delim.len() - 1);
+        self.buffer.extend_from_slice(input);
+
+        let found = self.buffer[search_from..]
+            .windows(delim.len())
+            .position(|window| window == delim)
+            .map(|pos| search_from + pos + delim.len());
+
+        match found {
+            Some(end) => {
+                self.buffer.truncate(end);
+                Ok((end - old_len, true))
+            }
+            None if self.buffer.len() > limit => {
+                Err(HttpError::protocol("Response line or head too long"))
+            }
+            None => Ok((input.len(), false)),
+        }
+    }
+}
+
+/// Read exactly one response from `reader`, leaving any following bytes unread
+///
+/// `on_frame` sees each head and body frame in order; `on_raw` sees the raw
+/// bytes the decoder consumed.
+pub fn read_response<R, F, G>(reader: &mut R, method: &str, mut on_frame: F, mut on_raw: G) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(Frame<'_>) -> Result<()>,
+    G: FnMut(&[u8]),
+{
+    let mut decoder = ResponseDecoder::new(method);
+
+    while !decoder.is_complete() {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            decoder.decode_eof()?;
+            break;
+        }
+
+        let (consumed, frame) = decoder.decode(buf)?;
+        on_raw(&buf[..consumed]);
+        if let Some(frame) = frame {
+            on_frame(frame)?;
+        }
+        reader.consume(consumed);
+    }
+
+    Ok(())
+}
+
+/// Async counterpart of [`read_response`]
+pub async fn read_response_async<R, F, G>(reader: &mut R, method: &str, mut on_frame: F, mut on_raw: G) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    F: FnMut(Frame<'_>) -> Result<()>,
+    G: FnMut(&[u8]),
+{
+    let mut decoder = ResponseDecoder::new(method);
+
+    while !decoder.is_complete() {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            decoder.decode_eof()?;
+            break;
+        }
+
+        let (consumed, frame) = decoder.decode(buf)?;
+        on_raw(&buf[..consumed]);
+        if let Some(frame) = frame {
+            on_frame(frame)?;
+        }
+        reader.consume(consumed);
+    }
+
+    Ok(())
+}
+
+/// Read up to and including the final response head, for streaming the body
+pub fn read_head<R: BufRead>(reader: &mut R, decoder: &mut ResponseDecoder) -> Result<ResponseHead> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            decoder.decode_eof()?;
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before response"));
+        }
+
+        let (consumed, frame) = decoder.decode(buf)?;
+        let head = match frame {
+            Some(Frame::Head(head)) => Some(head),
+            _ => None,
+        };
+        reader.consume(consumed);
+        if let Some(head) = head {
+            return Ok(head);
+        }
+    }
+}
+
+/// Read the next run of body bytes after [`read_head`], or `None` at the end
+pub fn read_chunk<R: BufRead>(reader: &mut R, decoder: &mut ResponseDecoder) -> Result<Option<Vec<u8>>> {
+    while !decoder.is_complete() {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            decoder.decode_eof()?;
+            break;
+        }
+
+        let (consumed, frame) = decoder.decode(buf)?;
+        let data = match frame {
+            Some(Frame::Data(data)) => Some(data.to_vec()),
+            _ => None,
+        };
+        reader.consume(consumed);
+        if data.is_some() {
+            return Ok(data);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Async counterpart of [`read_head`]
+pub async fn read_head_async<R: AsyncBufRead + Unpin>(reader: &mut R, decoder: &mut ResponseDecoder) -> Result<ResponseHead> {
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            decoder.decode_eof()?;
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before response"));
+        }
+
+        let (consumed, frame) = decoder.decode(buf)?;
+        let head = match frame {
+            Some(Frame::Head(head)) => Some(head),
+            _ => None,
+        };
+        reader.consume(consumed);
+        if let Some(head) = head {
+            return Ok(head);
+        }
+    }
+}
+
+/// Async counterpart of [`read_chunk`]
+pub async fn read_chunk_async<R: AsyncBufRead + Unpin>(reader: &mut R, decoder: &mut ResponseDecoder) -> Result<Option<Vec<u8>>> {
+    while !decoder.is_complete() {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            decoder.decode_eof()?;
+            break;
+        }
+
+        let (consumed, frame) = decoder.decode(buf)?;
+        let data = match frame {
+            Some(Frame::Data(data)) => Some(data.to_vec()),
+            _ => None,
+        };
+        reader.consume(consumed);
+        if data.is_some() {
+            return Ok(data);
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_all(input: &[u8], method: &str) -> Result<(ResponseHead, Vec<u8>, usize)> {
+        let mut cursor = Cursor::new(input);
+        let mut head = None;
+        let mut body = Vec::new();
+        read_response(&mut cursor, method, |frame| {
+            match frame {
+                Frame::Head(h) => head = Some(h),
+                Frame::Data(data) => body.extend_from_slice(data),
+            }
+            Ok(())
+        }, |_| {})?;
+        Ok((head.unwrap(), body, cursor.position() as usize))
+    }
+
+    #[test]
+    fn test_content_length_leaves_next_message() {
+        let input = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 204 No Content\r\n\r\n";
+        let (head, body, position) = read_all(input, "GET").unwrap();
+
+        assert_eq!(head.status, 200);
+        assert_eq!(body, b"hello");
+        assert_eq!(&input[position..], b"HTTP/1.1 204 No Content\r\n\r\n");
+    }
+
+    #[test]
+    fn test_chunked_with_trailers() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5;x=y\r\npedia\r\n0\r\nExpires: never\r\n\r\nNEXT";
+        let (_, body, position) = read_all(input, "GET").unwrap();
+
+        assert_eq!(body, b"Wikipedia");
+        assert_eq!(&input[position..], b"NEXT");
+    }
+
+    #[test]
+    fn test_no_body_cases() {
+        let input = b"HTTP/1.1 200 OK\r\nContent-Length: 1234\r\n\r\n";
+        let (_, body, _) = read_all(input, "HEAD").unwrap();
+        assert!(body.is_empty());
+
+        let input = b"HTTP/1.1 304 Not Modified\r\nContent-Length: 99\r\n\r\n";
+        let (head, body, _) = read_all(input, "GET").unwrap();
+        assert_eq!(head.status, 304);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_interim_response_and_close_delimited() {
+        let input = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.0 200 OK\r\n\r\nuntil close";
+        let (head, body, _) = read_all(input, "POST").unwrap();
+
+        assert_eq!(head.status, 200);
+        assert!(!head.keep_alive());
+        assert_eq!(body, b"until close");
+    }
+
+    #[test]
+    fn test_truncated_body_is_error() {
+        let input = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort";
+        let err = read_all(input, "GET").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_malformed_chunk_is_protocol_error() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n";
+        let err = read_all(input, "GET").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        match HttpError::find(&err) {
+            Some(HttpError::Protocol { message, source }) => {
+                assert!(message.starts_with("Invalid chunk size"));
+                assert!(source.as_ref().unwrap().is::<std::num::ParseIntError>());
+            }
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let mut decoder = ResponseDecoder::new("GET");
+        let mut body = Vec::new();
+
+        for byte in input.chunks(1) {
+            let (consumed, frame) = decoder.decode(byte).unwrap();
+            assert_eq!(consumed, 1);
+            if let Some(Frame::Data(data)) = frame {
+                body.extend_from_slice(data);
+            }
+        }
+        assert!(decoder.is_complete());
+        assert_eq!(body, b"abc");
+    }
+}
@@ -0,0 +1,260 @@
+use std::future::poll_fn;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use bytes::Bytes;
+use h2::RecvStream;
+use h2::client::SendRequest;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::timeout;
+
+use crate::body::BodyLimit;
+use crate::error::HttpError;
+use crate::headers::HeaderMap;
+use crate::http1::ResponseHead;
+use crate::http_client::{HttpRequest, HttpResponse, MachineHttpPriority};
+use crate::url::Url;
+
+/// ALPN protocol identifiers offered during the TLS handshake, preferred first
+pub const ALPN_PROTOCOLS: [&str; 2] = ["h2", "http/1.1"];
+
+/// Connection-specific header fields that HTTP/2 forbids (RFC 9113 §8.2.2)
+const CONNECTION_HEADERS: [&str; 6] = ["host", "connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// A multiplexed HTTP/2 connection
+///
+/// Cloning is cheap and every clone can open streams concurrently. HPACK,
+/// flow control, SETTINGS and GOAWAY are handled by a background task that
+/// drives the connection until the peer closes it.
+#[derive(Clone)]
+pub struct Http2Connection {
+    sender: SendRequest<Bytes>,
+    closed: Arc<AtomicBool>,
+    peer_certificate: Option<Arc<[u8]>>,
+}
+
+impl Http2Connection {
+    /// Perform the HTTP/2 preface and SETTINGS exchange over `io`
+    pub async fn handshake<T>(io: T) -> Result<Self>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, connection) = h2::client::handshake(io).await.map_err(h2_error)?;
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let driver_closed = closed.clone();
+        tokio::spawn(async move {
+            // Ends on GOAWAY, a connection error or once every handle is dropped
+            let _ = connection.await;
+            driver_closed.store(true, Ordering::Release);
+        });
+
+        Ok(Http2Connection { sender, closed, peer_certificate: None })
+    }
+
+    /// Remember the server's leaf certificate (DER) from the TLS handshake
+    pub fn with_peer_certificate(mut self, der: Option<Vec<u8>>) -> Self {
+        self.peer_certificate = der.map(Arc::from);
+        self
+    }
+
+    pub fn peer_certificate(&self) -> Option<&[u8]> {
+        self.peer_certificate.as_deref()
+    }
+
+    /// Whether both handles refer to the same underlying connection
+    pub fn ptr_eq(&self, other: &Http2Connection) -> bool {
+        Arc::ptr_eq(&self.closed, &other.closed)
+    }
+
+    /// Whether the connection can no longer open new streams
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Send `request` to `url` on a new stream and read the whole response
+    pub async fn send(&self, request: &HttpRequest, url: &Url, limit: Duration, max_body_size: Option<u64>) -> Result<HttpResponse> {
+        timeout(limit, async {
+            let (head, mut body) = self.send_head(request, url).await?;
+            let mut received = Vec::new();
+            let mut body_limit = BodyLimit::new(max_body_size);
+            body_limit.check_declared(&head, request.method())?;
+            while let Some(chunk) = body.next_chunk().await {
+                let chunk = chunk?;
+                body_limit.add(chunk.len())?;
+                received.extend_from_slice(&chunk);
+            }
+            HttpResponse::from_parts_limited(head, received, max_body_size)
+        }).await
+            .map_err(|_| HttpError::timeout("HTTP/2 request timed out"))?
+    }
+
+    /// Send `request` and wait only for the response head; the body is streamed
+    pub async fn send_streaming(&self, request: &HttpRequest, url: &Url, limit: Duration) -> Result<(ResponseHead, Http2Body)> {
+        timeout(limit, self.send_head(request, url)).await
+            .map_err(|_| HttpError::timeout("HTTP/2 request timed out"))?
+    }
+
+    async fn send_head(&self, request: &HttpRequest, url: &Url) -> Result<(ResponseHead, Http2Body)> {
+        let mut builder = http::Request::builder()
+            .method(request.method())
+            .uri(format!("{}://{}{}", url.scheme, url.host_header(), url.request_target()));
+
+        for (name, value) in &request.header_fields(&url.host_header()) {
+            if !CONNECTION_HEADERS.iter().any(|forbidden| name.eq_ignore_ascii_case(forbidden)) {
+                builder = builder.header(name, value);
+            }
+        }
+        // A caller-set Priority field wins over the machine priority's mapping
+        if let Some(priority) = request.machine_priority()
+            && !request.headers().contains("priority")
+        {
+            builder = builder.header("priority", priority_field(priority));
+        }
+
+        let http_request = builder.body(())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid HTTP/2 request: {}", e)))?;
+
+        // Waits for the peer's SETTINGS_MAX_CONCURRENT_STREAMS to allow another stream
+        let mut sender = self.sender.clone().ready().await.map_err(h2_error)?;
+        let body = request.body().unwrap_or("");
+        let (response, mut stream) = sender.send_request(http_request, body.is_empty()).map_err(h2_error)?;
+
+        let mut data = Bytes::copy_from_slice(body.as_bytes());
+        while !data.is_empty() {
+            stream.reserve_capacity(data.len());
+            let capacity = poll_fn(|cx| stream.poll_capacity(cx)).await
+                .ok_or_else(|| Error::new(ErrorKind::ConnectionAborted, "HTTP/2 stream closed while sending body"))?
+                .map_err(h2_error)?;
+            let chunk = data.split_to(capacity.min(data.len()));
+            stream.send_data(chunk, data.is_empty()).map_err(h2_error)?;
+        }
+
+        let (parts, recv) = response.await.map_err(h2_error)?.into_parts();
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &parts.headers {
+            headers.append(name.as_str(), String::from_utf8_lossy(value.as_bytes()).trim());
+        }
+        let head = ResponseHead {
+            version: "HTTP/2".to_string(),
+            status: parts.status.as_u16(),
+            status_text: parts.status.canonical_reason().unwrap_or("").to_string(),
+            headers,
+        };
+
+        Ok((head, Http2Body { recv }))
+    }
+}
+
+/// Body of an HTTP/2 response, read as it arrives
+pub struct Http2Body {
+    recv: RecvStream,
+}
+
+impl Http2Body {
+    /// Next DATA payload, or `None` at the end of the stream
+    pub async fn next_chunk(&mut self) -> Option<Result<Vec<u8>>> {
+        let chunk = match self.recv.data().await? {
+            Ok(chunk) => chunk,
+            Err(e) => return Some(Err(h2_error(e))),
+        };
+        // Reopen the flow-control window for what we consumed
+        if let Err(e) = self.recv.flow_control().release_capacity(chunk.len()) {
+            return Some(Err(h2_error(e)));
+        }
+        Some(Ok(chunk.to_vec()))
+    }
+}
+
+/// Map a Machine-HTTP priority to an RFC 9218 `priority` field value
+///
+/// HTTP/2 PRIORITY frames are deprecated (RFC 9113 §5.3.2), so stream
+/// priority is signalled with the Extensible Priorities header instead.
+pub fn priority_field(priority: MachineHttpPriority) -> &'static str {
+    match priority {
+        MachineHttpPriority::Latency => "u=1",
+        MachineHttpPriority::Accuracy => "u=3",
+        MachineHttpPriority::Throughput => "u=5, i",
+    }
+}
+
+/// Convert an h2 error; refused streams stay retryable (see [`HttpError::is_refused_stream`])
+fn h2_error(error: h2::Error) -> Error {
+    if error.is_io() {
+        return error.into_io().unwrap_or_else(|| Error::other("HTTP/2 I/O error"));
+    }
+    HttpError::Protocol { message: format!("HTTP/2 error: {}", error), source: Some(error.into()) }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_multiplexed_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut connection = h2::server::handshake(socket).await.unwrap();
+            let mut handlers = Vec::new();
+
+            while let Some(accepted) = connection.accept().await {
+                let (request, mut respond) = accepted.unwrap();
+                handlers.push(tokio::spawn(async move {
+                    let path = request.uri().path().to_string();
+                    let priority: Vec<_> = request.headers().get_all("priority").iter().map(|v| v.to_str().unwrap().to_string()).collect();
+                    let mut body = request.into_body();
+                    let mut received = Vec::new();
+                    while let Some(chunk) = body.data().await {
+                        received.extend_from_slice(&chunk.unwrap());
+                    }
+
+                    let reply = format!("{} {:?} {}", path, priority, String::from_utf8(received).unwrap());
+                    let response = http::Response::builder().status(200).body(()).unwrap();
+                    let mut stream = respond.send_response(response, false).unwrap();
+                    stream.send_data(Bytes::from(reply), true).unwrap();
+                }));
+            }
+            for handler in handlers {
+                handler.await.unwrap();
+            }
+        });
+
+        let socket = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let connection = Http2Connection::handshake(socket).await.unwrap();
+        let base = Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap();
+        let (one, two) = (base.join("/one").unwrap(), base.join("/two").unwrap());
+
+        let mut first = HttpRequest::new("GET", "/one");
+        first.set_machine_priority(MachineHttpPriority::Latency);
+        let mut second = HttpRequest::new("POST", "/two");
+        second.set_body("payload");
+        let mut third = HttpRequest::new("GET", "/three");
+        third.set_machine_priority(MachineHttpPriority::Latency);
+        third.set_header("Priority", "u=0");
+        let three = base.join("/three").unwrap();
+
+        // All requests are in flight on the same connection at once
+        let (first, second, third) = tokio::join!(
+            connection.send(&first, &one, Duration::from_secs(5), None),
+            connection.send(&second, &two, Duration::from_secs(5), None),
+            connection.send(&third, &three, Duration::from_secs(5), None),
+        );
+        let (first, second, third) = (first.unwrap(), second.unwrap(), third.unwrap());
+
+        assert_eq!(first.version, "HTTP/2");
+        assert_eq!(first.status, 200);
+        assert_eq!(first.text().unwrap(), "/one [\"u=1\"] ");
+        assert_eq!(second.text().unwrap(), "/two [] payload");
+        assert_eq!(third.text().unwrap(), "/three [\"u=0\"] ");
+
+        drop(connection);
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+// Module declarations - declare Rust modules from separate files
+// Each module corresponds to a different component of the Biosurf browser
+pub mod http_client;    // Custom HTTP client with machine-specific extensions
+pub mod http1;          // HTTP/1.1 response framing
+pub mod http2;          // HTTP/2 connections (ALPN, multiplexing)
+pub mod error;          // Typed HttpError carried inside io::Error
+pub mod url;            // URL parsing and percent-encoding
+pub mod headers;        // Case-insensitive, ordered multi-value headers
+pub mod body;           // Streaming response bodies and size limits
+pub mod charset;        // Charset detection (BOM, Content-Type, <meta>) and decoding
+pub mod compression;    // Content-Encoding decoding (gzip, deflate, brotli)
+pub mod redirect;       // Redirect policy and method rewriting
+pub mod retry;          // Retry policy with backoff and Retry-After
+pub mod proxy;          // HTTP CONNECT and SOCKS5 proxy tunnels
+pub mod tls;            // TLS configuration (roots, client certificates, pinning)
+pub mod sse;            // Server-Sent Events client
+pub mod websocket;      // RFC 6455 WebSocket client with permessage-deflate
+pub mod machine_http;   // Machine-HTTP response headers and capability discovery
+pub mod field_cache;    // Client-side X-Machine-Field-Cache enforcement (JSONPath)
+pub mod download;       // Range requests and resumable downloads
+pub mod cache;          // RFC 9111 response cache (memory and disk)
+pub mod timing;         // Per-phase timeouts and request timings
+pub mod dns;            // DNS resolution functionality
+pub mod auth;           // Basic, Digest, Bearer and OAuth2 client-credentials authentication
+pub mod middleware;     // Request/response middleware stack around execute
+pub mod pipeline;       // HTTP/1.1 pipelining for Throughput batches
+pub mod scheduler;      // Priority-aware connection permits and batch execution
+pub mod connection_pool; // Connection pooling for network efficiency
+pub mod deterministic;  // Deterministic execution utilities
+pub mod dom;            // Document Object Model handling
+pub mod session_manager; // Browser session management
+#[cfg(test)]
+mod test_support;       // Local servers and clients shared by tests
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use crate::headers::HeaderMap;
+use crate::http_client::MachineHttpPriority;
+
+/// Priority the server applied, e.g. `X-Machine-Priority-Ack: latency`
+pub const PRIORITY_ACK: &str = "X-Machine-Priority-Ack";
+
+/// Per-field outcome of `X-Machine-Field-Cache`, e.g.
+/// `X-Machine-Field-Cache-Status: $.items[*].price=hit;age=60s, $.total=miss`
+pub const FIELD_CACHE_STATUS: &str = "X-Machine-Field-Cache-Status";
+
+/// Seed the server used for a deterministic request, e.g. `X-Machine-Deterministic-Seed: 42`
+pub const DETERMINISTIC_SEED: &str = "X-Machine-Deterministic-Seed";
+
+/// Machine-HTTP extensions a server supports, e.g.
+/// `X-Machine-Capabilities: version=1, priority, field-cache, deterministic`
+pub const CAPABILITIES: &str = "X-Machine-Capabilities";
+
+/// What the server did with one field cache directive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldCacheOutcome {
+    /// Served from the server's field cache
+    Hit,
+    /// Computed fresh and cached
+    Miss,
+    /// Served stale while the server revalidates
+    Stale,
+    /// The directive was understood but not applied
+    Ignored,
+    /// A status this client does not know
+    Other(String),
+}
+
+impl FieldCacheOutcome {
+    fn parse(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "hit" => FieldCacheOutcome::Hit,
+            "miss" => FieldCacheOutcome::Miss,
+            "stale" => FieldCacheOutcome::Stale,
+            "ignored" => FieldCacheOutcome::Ignored,
+            other => FieldCacheOutcome::Other(other.to_string()),
+        }
+    }
+}
+
+/// One entry of `X-Machine-Field-Cache-Status`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldCacheStatus {
+    pub field_path: String,
+    pub outcome: FieldCacheOutcome,
+    /// Age of the served value, if the server reported one
+    pub age: Option<Duration>,
+}
+
+/// Machine-HTTP response headers, parsed
+///
+/// Fields are empty when the server sent nothing back, which usually means
+/// it does not implement the extensions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineHttpMeta {
+    /// Priority the server acknowledged
+    pub priority: Option<MachineHttpPriority>,
+    /// Per-field cache outcomes, in the order the server listed them
+    pub field_cache: Vec<FieldCacheStatus>,
+    /// Seed echoed for a deterministic request
+    pub deterministic_seed: Option<u64>,
+}
+
+impl MachineHttpMeta {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let field_cache = headers.get_all(FIELD_CACHE_STATUS)
+            .flat_map(split_list)
+            .filter_map(|item| {
+                let mut parameters = item.split(';').map(str::trim);
+                let (field_path, outcome) = parameters.next()?.rsplit_once('=')?;
+                let age = parameters
+                    .filter_map(|parameter| parameter.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("age"))
+                    .and_then(|(_, value)| parse_seconds(value));
+                Some(FieldCacheStatus {
+                    field_path: field_path.trim().to_string(),
+                    outcome: FieldCacheOutcome::parse(outcome.trim()),
+                    age,
+                })
+            })
+            .collect();
+
+        MachineHttpMeta {
+            priority: headers.get(PRIORITY_ACK).and_then(|value| MachineHttpPriority::parse(value.trim())),
+            field_cache,
+            deterministic_seed: headers.get(DETERMINISTIC_SEED).and_then(|value| value.trim().parse().ok()),
+        }
+    }
+
+    /// Whether the server sent any Machine-HTTP response header
+    pub fn is_empty(&self) -> bool {
+        self.priority.is_none() && self.field_cache.is_empty() && self.deterministic_seed.is_none()
+    }
+
+    /// The server's status for one directive's field path
+    pub fn field(&self, field_path: &str) -> Option<&FieldCacheStatus> {
+        self.field_cache.iter().find(|status| status.field_path == field_path)
+    }
+}
+
+/// Machine-HTTP extensions advertised by a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineCapabilities {
+    /// Protocol version, from a `version=N` token
+    pub version: Option<u32>,
+    pub priority: bool,
+    pub field_cache: bool,
+    pub deterministic: bool,
+    /// Tokens this client does not know, lowercased
+    pub other: Vec<String>,
+}
+
+impl MachineCapabilities {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut capabilities = MachineCapabilities::default();
+
+        for token in headers.get_all(CAPABILITIES).flat_map(|value| value.split(',')) {
+            let token = token.trim().to_ascii_lowercase();
+            match token.split_once('=') {
+                Some(("version", version)) => capabilities.version = version.trim().parse().ok(),
+                _ => match token.as_str() {
+                    "priority" => capabilities.priority = true,
+                    "field-cache" => capabilities.field_cache = true,
+                    "deterministic" => capabilities.deterministic = true,
+                    "" => {}
+                    _ => capabilities.other.push(token),
+                },
+            }
+        }
+
+        capabilities
+    }
+
+    /// Whether the server advertised Machine-HTTP at all
+    pub fn is_supported(&self) -> bool {
+        self.version.is_some() || self.priority || self.field_cache || self.deterministic || !self.other.is_empty()
+    }
+}
+
+/// Split a comma-separated list, ignoring commas inside brackets or quotes
+///
+/// Field paths such as `$['a,b']` may contain commas themselves.
+fn split_list(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut quote, mut start) = (0usize, None, 0);
+
+    for (position, c) in value.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (_, Some(_)) => {}
+            ('[', None) => depth += 1,
+            (']', None) => depth = depth.saturating_sub(1),
+            (',', None) if depth == 0 => {
+                items.push(value[start..position].trim());
+                start = position + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(value[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Seconds with an optional `s` suffix, as in `X-Machine-Field-Cache`
+fn parse_seconds(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    value.strip_suffix('s').unwrap_or(value).parse().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response_meta() {
+        let headers = HeaderMap::parse_lines([
+            "X-Machine-Priority-Ack: Latency",
+            "X-Machine-Field-Cache-Status: $.data.items[*].price=hit;age=60s, $['a,b']=stale",
+            "X-Machine-Field-Cache-Status: $.total=bypassed",
+            "X-Machine-Deterministic-Seed: 42",
+        ]);
+        let meta = MachineHttpMeta::from_headers(&headers);
+
+        assert_eq!(meta.priority, Some(MachineHttpPriority::Latency));
+        assert_eq!(meta.deterministic_seed, Some(42));
+        assert_eq!(meta.field("$.data.items[*].price"), Some(&FieldCacheStatus {
+            field_path: "$.data.items[*].price".to_string(),
+            outcome: FieldCacheOutcome::Hit,
+            age: Some(Duration::from_secs(60)),
+        }));
+        assert_eq!(meta.field("$['a,b']").unwrap().outcome, FieldCacheOutcome::Stale);
+        assert_eq!(meta.field("$.total").unwrap().outcome, FieldCacheOutcome::Other("bypassed".to_string()));
+        assert!(MachineHttpMeta::from_headers(&HeaderMap::new()).is_empty());
+
+        let capabilities = MachineCapabilities::from_headers(&HeaderMap::parse_lines(["X-Machine-Capabilities: version=1, priority, Field-Cache, batching"]));
+        assert_eq!(capabilities, MachineCapabilities {
+            version: Some(1),
+            priority: true,
+            field_cache: true,
+            deterministic: false,
+            other: vec!["batching".to_string()],
+        });
+    }
+}
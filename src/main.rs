@@ -1,11 +1,5 @@
-// Module declarations - declare Rust modules from separate files
-// Each module corresponds to a different component of the Biosurf browser
-mod http_client;        // Custom HTTP client with machine-specific extensions
-mod dns;                // DNS resolution functionality
-mod connection_pool;    // Connection pooling for network efficiency
-mod deterministic;      // Deterministic execution utilities
-mod dom;                // Document Object Model handling
-mod session_manager;    // Browser session management
+// Components live in the library crate so they can be used from other programs
+use biosurf::{connection_pool, deterministic, dns, dom, http_client, session_manager};
 
 // Entry point of the application
 // `#[tokio::main]` macro configures the Tokio runtime for async operations
@@ -0,0 +1,149 @@
+use std::future::Future;
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::http_client::{HttpClient, HttpRequest, HttpResponse};
+
+/// Boxed future returned by [`Middleware::handle`]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A layer around `HttpClient::execute`
+///
+/// Simple middleware only implements the hooks: `on_request` runs before
+/// the request is sent and `on_response` after the response is parsed.
+/// Middleware that needs to decide whether or how often to send, such as
+/// retries, caching or re-authentication, overrides `handle` and calls
+/// [`Next::run`] zero or more times.
+///
+/// Streamed requests (`execute_stream`, downloads, event sources and
+/// pipelined batches) cannot be replayed, so they only pass through
+/// `on_stream_request`.
+///
+/// An error from any hook aborts the request with that error.
+pub trait Middleware: Send + Sync {
+    fn on_request(&self, request: &mut HttpRequest) -> Result<()> {
+        let _ = request;
+        Ok(())
+    }
+
+    fn on_response(&self, request: &HttpRequest, response: &mut HttpResponse) -> Result<()> {
+        let _ = (request, response);
+        Ok(())
+    }
+
+    fn handle<'a>(&'a self, mut request: HttpRequest, next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            self.on_request(&mut request)?;
+            let mut response = next.run(request.clone()).await?;
+            self.on_response(&request, &mut response)?;
+            Ok(response)
+        })
+    }
+
+    /// Prepare a request whose response is streamed instead of returned from `handle`
+    ///
+    /// Runs `on_request` by default. Middleware that overrides `handle` to
+    /// change the request should do the same here, as far as it can
+    /// without seeing the response.
+    fn on_stream_request<'a>(&'a self, mut request: HttpRequest, client: &'a HttpClient) -> BoxFuture<'a, Result<HttpRequest>> {
+        let _ = client;
+        Box::pin(async move {
+            self.on_request(&mut request)?;
+            Ok(request)
+        })
+    }
+}
+
+/// The rest of the middleware stack, ending in the client itself
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    client: &'a HttpClient,
+    middleware: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(client: &'a HttpClient, middleware: &'a [Arc<dyn Middleware>]) -> Self {
+        Next { client, middleware }
+    }
+
+    /// The client the stack belongs to
+    pub fn client(&self) -> &'a HttpClient {
+        self.client
+    }
+
+    /// Pass the request to the next layer and return its response
+    pub async fn run(self, request: HttpRequest) -> Result<HttpResponse> {
+        match self.middleware.split_first() {
+            Some((layer, rest)) => layer.handle(request, Next::new(self.client, rest)).await,
+            None => self.client.dispatch(&request).await,
+        }
+    }
+
+    /// Pass a streamed request through `on_stream_request` of every remaining layer, in order
+    pub(crate) async fn prepare_stream(self, mut request: HttpRequest) -> Result<HttpRequest> {
+        for layer in self.middleware {
+            request = layer.on_stream_request(request, self.client).await?;
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{pooled_client, spawn_server};
+    use std::sync::Mutex;
+
+    /// Records the order hooks run in and tags requests
+    struct Tag(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Tag {
+        fn on_request(&self, request: &mut HttpRequest) -> Result<()> {
+            self.1.lock().unwrap().push(format!("request {}", self.0));
+            request.add_header("X-Layer", self.0);
+            Ok(())
+        }
+
+        fn on_response(&self, _request: &HttpRequest, response: &mut HttpResponse) -> Result<()> {
+            self.1.lock().unwrap().push(format!("response {}", self.0));
+            response.headers.append("X-Seen-By", self.0);
+            Ok(())
+        }
+    }
+
+    /// Answers /cached itself without calling the rest of the stack
+    struct ShortCircuit;
+
+    impl Middleware for ShortCircuit {
+        fn handle<'a>(&'a self, request: HttpRequest, next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+            Box::pin(async move {
+                if request.path().ends_with("/cached") {
+                    return HttpResponse::parse(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nlocal");
+                }
+                next.run(request).await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_order_and_short_circuit() {
+        let (port, server) = spawn_server(vec!["HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nremote".to_string()]);
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut client = pooled_client();
+        client.add_middleware(Tag("outer", log.clone()))
+            .add_middleware(ShortCircuit)
+            .add_middleware(Tag("inner", log.clone()));
+
+        let response = client.execute(&HttpRequest::new("GET", &format!("http://127.0.0.1:{}/data", port))).await.unwrap();
+        assert_eq!(response.text().unwrap(), "remote");
+        assert_eq!(response.headers.get_all("x-seen-by").collect::<Vec<_>>(), ["inner", "outer"]);
+        assert_eq!(*log.lock().unwrap(), ["request outer", "request inner", "response inner", "response outer"]);
+        assert!(server.join().unwrap()[0].contains("X-Layer: outer\r\nX-Layer: inner\r\n"));
+
+        let cached = client.execute(&HttpRequest::new("GET", &format!("http://127.0.0.1:{}/cached", port))).await.unwrap();
+        assert_eq!(cached.text().unwrap(), "local");
+        assert_eq!(cached.headers.get_all("x-seen-by").collect::<Vec<_>>(), ["outer"]);
+    }
+}
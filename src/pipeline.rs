@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::connection_pool::ConnectionKey;
+use crate::http_client::{HttpClient, HttpRequest, HttpResponse};
+use crate::retry::is_idempotent;
+use crate::scheduler::collect_ordered;
+use crate::url::Url;
+
+/// Whether a request may share a pipeline: idempotent methods only (RFC 9112 §9.3.2)
+pub fn is_pipelinable(request: &HttpRequest) -> bool {
+    is_idempotent(request.method())
+}
+
+/// Requests bound for one connection, with their positions in the batch
+///
+/// Requests that cannot be pipelined get a pipeline of their own with no key.
+struct Pipeline {
+    key: Option<ConnectionKey>,
+    indexes: Vec<usize>,
+    requests: Vec<HttpRequest>,
+}
+
+/// Send `requests` over HTTP/1.1 pipelines, returning results in request order
+///
+/// Pipelinable requests to the same host are written back-to-back, up to
+/// `depth` per connection, and their responses are matched in order. At
+/// most `connections` pipelines run at once. Requests the server does not
+/// answer in order, because it closed the connection early or echoed
+/// another request's `X-Request-Id`, are sent again one at a time, as are
+/// requests that cannot be pipelined and hosts that speak HTTP/2. Pipelined
+/// requests without an `X-Request-Id` are sent with a generated one.
+///
+/// This works below `execute`: middleware, caches, redirects and retries
+/// are not applied. `HttpClient::execute_batch` only pipelines on clients
+/// without middleware or a response cache.
+pub async fn execute_pipelined(client: &HttpClient, requests: Vec<HttpRequest>, depth: usize, connections: usize) -> Vec<Result<HttpResponse>> {
+    let count = requests.len();
+    let mut pipelines: Vec<Pipeline> = Vec::new();
+    // The pipeline still being filled for each connection key
+    let mut filling: HashMap<ConnectionKey, usize> = HashMap::new();
+
+    for (index, request) in requests.into_iter().enumerate() {
+        let key = match Url::parse(request.path()) {
+            Ok(url) if is_pipelinable(&request) => client.connection_key(&url, &request),
+            _ => {
+                pipelines.push(Pipeline { key: None, indexes: vec![index], requests: vec![request] });
+                continue;
+            }
+        };
+        let position = match filling.get(&key) {
+            Some(&position) if pipelines[position].requests.len() < depth.max(1) => position,
+            _ => {
+                filling.insert(key.clone(), pipelines.len());
+                pipelines.push(Pipeline { key: Some(key), indexes: Vec::new(), requests: Vec::new() });
+                pipelines.len() - 1
+            }
+        };
+        pipelines[position].indexes.push(index);
+        pipelines[position].requests.push(request);
+    }
+
+    let slots = Arc::new(Semaphore::new(connections.max(1)));
+    let mut tasks = JoinSet::new();
+    for pipeline in pipelines {
+        let (client, slots) = (client.clone(), slots.clone());
+        tasks.spawn(async move {
+            let _slot = slots.acquire().await;
+            let matched = match pipeline.key {
+                Some(key) if pipeline.requests.len() > 1 => client.send_pipelined(key, &pipeline.requests).await,
+                _ => Vec::new(),
+            };
+
+            let answered = matched.len();
+            let mut results: Vec<_> = pipeline.indexes.iter().copied().zip(matched.into_iter().map(Ok)).collect();
+            // Fall back to one request at a time for the rest
+            for (&index, request) in pipeline.indexes[answered..].iter().zip(&pipeline.requests[answered..]) {
+                results.push((index, client.execute_direct(request).await));
+            }
+            results
+        });
+    }
+    collect_ordered(tasks, count).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MachineHttpPriority;
+    use crate::middleware::Middleware;
+    use crate::test_support::{listen, pooled_client, read_head, spawn_server_per_request};
+    use std::io::{BufReader, Write};
+
+    #[tokio::test]
+    async fn test_pipelined_batch_falls_back_on_misordered_response() {
+        let (listener, port) = listen();
+        let server = std::thread::spawn(move || {
+            // All three requests arrive before any response is sent
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let pipelined: Vec<String> = (0..3).map(|_| read_head(&mut reader)).collect();
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nX-Request-Id: 1\r\nContent-Length: 3\r\n\r\none\
+                HTTP/1.1 200 OK\r\nX-Request-Id: 3\r\nContent-Length: 5\r\n\r\nthree").unwrap();
+
+            let mut fallback = Vec::new();
+            for body in ["two", "three"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                fallback.push(read_head(&mut reader));
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            (pipelined, fallback)
+        });
+
+        let mut client = pooled_client();
+        client.set_pipeline_depth(3);
+        let requests = (1..=3).map(|id| {
+            let mut request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/item/{}", port, id));
+            request.add_header("X-Request-Id", &id.to_string());
+            request.set_machine_priority(MachineHttpPriority::Throughput);
+            request
+        }).collect();
+
+        let bodies: Vec<String> = client.execute_batch(requests).await.into_iter()
+            .map(|result| result.unwrap().text().unwrap())
+            .collect();
+        assert_eq!(bodies, ["one", "two", "three"]);
+
+        let (pipelined, fallback) = server.join().unwrap();
+        assert!(pipelined[0].starts_with("GET /item/1 ") && pipelined[2].starts_with("GET /item/3 "));
+        assert!(fallback[0].starts_with("GET /item/2 ") && fallback[1].starts_with("GET /item/3 "));
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_get_generated_request_ids() {
+        let (listener, port) = listen();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let pipelined: Vec<String> = (0..2).map(|_| read_head(&mut reader)).collect();
+            // Answer the second request first, echoing its id
+            let second_id = pipelined[1].lines().find_map(|line| line.strip_prefix("X-Request-Id: ")).unwrap().to_string();
+            let response = format!("HTTP/1.1 200 OK\r\nX-Request-Id: {}\r\nContent-Length: 3\r\n\r\ntwo", second_id);
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+            for body in ["one", "two"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                read_head(&mut reader);
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\n{}", body);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            pipelined
+        });
+
+        let mut client = pooled_client();
+        client.set_pipeline_depth(2);
+        let requests = (1..=2).map(|id| {
+            let mut request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/item/{}", port, id));
+            request.set_machine_priority(MachineHttpPriority::Throughput);
+            request
+        }).collect();
+
+        let bodies: Vec<String> = client.execute_batch(requests).await.into_iter()
+            .map(|result| result.unwrap().text().unwrap())
+            .collect();
+        assert_eq!(bodies, ["one", "two"]);
+
+        let pipelined = server.join().unwrap();
+        assert!(pipelined[0].contains("X-Request-Id: pipelined-1\r\n") && pipelined[1].contains("X-Request-Id: pipelined-2\r\n"));
+    }
+
+    /// Tags every request that passes through it
+    struct Tag;
+
+    impl Middleware for Tag {
+        fn on_request(&self, request: &mut HttpRequest) -> Result<()> {
+            request.add_header("X-Tagged", "yes");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_with_middleware_is_not_pipelined() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string();
+        let (port, server) = spawn_server_per_request(vec![response.clone(), response]);
+
+        let mut client = pooled_client();
+        client.set_pipeline_depth(2).add_middleware(Tag);
+        let requests = (1..=2).map(|id| {
+            let mut request = HttpRequest::new("GET", &format!("http://127.0.0.1:{}/item/{}", port, id));
+            request.set_machine_priority(MachineHttpPriority::Throughput);
+            request
+        }).collect();
+
+        for result in client.execute_batch(requests).await {
+            assert_eq!(result.unwrap().status, 200);
+        }
+        // One request per connection, each through the middleware
+        let requests = server.join().unwrap();
+        assert!(requests.iter().all(|request| request.contains("X-Tagged: yes\r\n") && !request.contains("X-Request-Id")));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::url::Url;
+
+/// Headers that carry credentials and are dropped on cross-origin redirects
+pub const SENSITIVE_HEADERS: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+
+/// Controls which redirects `HttpClient::execute` follows
+#[derive(Debug, Clone)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    same_origin_only: bool,
+    allowed_hosts: Vec<String>,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RedirectPolicy {
+    /// Follow up to 10 redirects to any host
+    pub fn new() -> Self {
+        RedirectPolicy {
+            max_redirects: 10,
+            same_origin_only: false,
+            allowed_hosts: Vec::new(),
+        }
+    }
+
+    /// Never follow redirects; the 3xx response is returned as-is
+    pub fn none() -> Self {
+        let mut policy = Self::new();
+        policy.max_redirects = 0;
+        policy
+    }
+
+    /// Maximum number of hops before `execute` fails
+    pub fn set_max_redirects(&mut self, max_redirects: usize) -> &mut Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Only follow redirects that keep the scheme, host and port
+    pub fn set_same_origin_only(&mut self, same_origin_only: bool) -> &mut Self {
+        self.same_origin_only = same_origin_only;
+        self
+    }
+
+    /// Restrict redirects to these hosts (and their subdomains)
+    pub fn allow_host(&mut self, host: &str) -> &mut Self {
+        self.allowed_hosts.push(host.trim_start_matches('.').to_ascii_lowercase());
+        self
+    }
+
+    pub fn max_redirects(&self) -> usize {
+        self.max_redirects
+    }
+
+    /// Whether a redirect from `from` to `to` may be followed
+    pub fn allows(&self, from: &Url, to: &Url) -> bool {
+        if self.same_origin_only && !from.same_origin(to) {
+            return false;
+        }
+
+        self.allowed_hosts.is_empty()
+            || self.allowed_hosts.iter().any(|allowed| {
+                to.host == *allowed || to.host.strip_suffix(allowed.as_str()).is_some_and(|sub| sub.ends_with('.'))
+            })
+    }
+}
+
+/// One followed redirect: the URL that answered with `status` and its target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub status: u16,
+    pub from: String,
+    pub to: String,
+}
+
+/// Whether a status code asks the client to follow the Location header
+pub fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Method to use for the next hop, and whether the request body is kept
+///
+/// 303 always becomes GET (HEAD stays HEAD); 301 and 302 turn POST into GET
+/// as browsers do; 307 and 308 repeat the request unchanged.
+pub fn redirect_method(status: u16, method: &str) -> (String, bool) {
+    match status {
+        303 if !method.eq_ignore_ascii_case("HEAD") => ("GET".to_string(), false),
+        301 | 302 if method.eq_ignore_ascii_case("POST") => ("GET".to_string(), false),
+        _ => (method.to_string(), true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_method_rewriting() {
+        assert_eq!(redirect_method(303, "PUT"), ("GET".to_string(), false));
+        assert_eq!(redirect_method(303, "HEAD"), ("HEAD".to_string(), true));
+        assert_eq!(redirect_method(302, "POST"), ("GET".to_string(), false));
+        assert_eq!(redirect_method(301, "DELETE"), ("DELETE".to_string(), true));
+        assert_eq!(redirect_method(307, "POST"), ("POST".to_string(), true));
+        assert_eq!(redirect_method(308, "POST"), ("POST".to_string(), true));
+    }
+
+    #[test]
+    fn test_policy_origin_and_allowlist() {
+        let from = Url::parse("https://example.com/a").unwrap();
+        let same = Url::parse("https://example.com/b").unwrap();
+        let sub = Url::parse("https://api.example.com/").unwrap();
+        let other = Url::parse("https://badexample.com/").unwrap();
+
+        let mut policy = RedirectPolicy::new();
+        assert!(policy.allows(&from, &other));
+
+        policy.set_same_origin_only(true);
+        assert!(policy.allows(&from, &same));
+        assert!(!policy.allows(&from, &sub));
+
+        let mut policy = RedirectPolicy::new();
+        policy.allow_host("example.com");
+        assert!(policy.allows(&from, &sub));
+        assert!(!policy.allows(&from, &other));
+    }
+}
@@ -0,0 +1,256 @@
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::deterministic::DeterministicRng;
+use crate::error::HttpError;
+
+/// Seed for backoff jitter on requests in deterministic mode
+const DETERMINISTIC_JITTER_SEED: u64 = 0x5245_5452_5921;
+
+/// When and how `HttpClient::execute` retries failed requests
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    retry_statuses: Vec<u16>,
+    retry_non_idempotent: bool,
+    jitter_seed: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Up to 3 retries, backing off from 100ms to at most 10s
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            retry_statuses: vec![429, 502, 503, 504],
+            retry_non_idempotent: false,
+            jitter_seed: DETERMINISTIC_JITTER_SEED,
+        }
+    }
+
+    /// Never retry
+    pub fn none() -> Self {
+        let mut policy = Self::new();
+        policy.max_retries = 0;
+        policy
+    }
+
+    pub fn set_max_retries(&mut self, max_retries: u32) -> &mut Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry; doubled for each further attempt
+    pub fn set_base_delay(&mut self, base_delay: Duration) -> &mut Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Upper bound for backoff; a longer Retry-After gives up instead
+    pub fn set_max_delay(&mut self, max_delay: Duration) -> &mut Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Response statuses that trigger a retry
+    pub fn set_retry_statuses(&mut self, statuses: &[u16]) -> &mut Self {
+        self.retry_statuses = statuses.to_vec();
+        self
+    }
+
+    /// Also retry POST/PATCH after failures where the server may have acted
+    pub fn set_retry_non_idempotent(&mut self, enabled: bool) -> &mut Self {
+        self.retry_non_idempotent = enabled;
+        self
+    }
+
+    /// Seed for jitter when the request is in deterministic mode
+    pub fn set_jitter_seed(&mut self, seed: u64) -> &mut Self {
+        self.jitter_seed = seed;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Jitter source: seeded from the policy in deterministic mode, randomly otherwise
+    pub fn jitter_rng(&self, deterministic: bool) -> DeterministicRng {
+        if deterministic {
+            DeterministicRng::new(self.jitter_seed)
+        } else {
+            DeterministicRng::new(rand::random())
+        }
+    }
+
+    /// Whether a response status may be retried for this method
+    ///
+    /// 429 means the request was rejected before processing, so it is safe
+    /// for every method; other statuses only for idempotent ones.
+    pub fn should_retry_status(&self, method: &str, status: u16) -> bool {
+        self.retry_statuses.contains(&status)
+            && (status == 429 || self.retry_non_idempotent || is_idempotent(method))
+    }
+
+    /// Whether a transport error may be retried for this method
+    ///
+    /// A typed [`HttpError`] is retried when it is transient: DNS and connect
+    /// failures for every method, since the request never reached the server,
+    /// and timeouts only for idempotent ones. For other errors a refused
+    /// connection is safe for every method; resets and timeouts only for
+    /// idempotent ones.
+    pub fn should_retry_error(&self, method: &str, error: &Error) -> bool {
+        if let Some(http_error) = HttpError::find(error) {
+            return http_error.is_transient() && match http_error {
+                HttpError::Dns { .. } | HttpError::Connect { .. } => true,
+                _ => self.retry_non_idempotent || is_idempotent(method),
+            };
+        }
+        match error.kind() {
+            ErrorKind::ConnectionRefused => true,
+            ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::TimedOut => self.retry_non_idempotent || is_idempotent(method),
+            _ => false,
+        }
+    }
+
+    /// Exponential backoff with equal jitter for the given retry (0-based)
+    pub fn backoff(&self, attempt: u32, rng: &mut DeterministicRng) -> Duration {
+        let exponential = self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let half = exponential / 2;
+        half + half.mul_f64(rng.next_f64())
+    }
+
+    /// Delay before the next attempt, honoring Retry-After when present
+    ///
+    /// Returns `None` when the server asks us to wait longer than `max_delay`.
+    pub fn delay(&self, attempt: u32, retry_after: Option<&str>, rng: &mut DeterministicRng) -> Option<Duration> {
+        match retry_after.and_then(|value| parse_retry_after(value, SystemTime::now())) {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt, rng)),
+        }
+    }
+}
+
+/// Methods that can be repeated without changing the outcome (RFC 9110 §9.2.2)
+pub fn is_idempotent(method: &str) -> bool {
+    matches!(method.to_ascii_uppercase().as_str(), "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE")
+}
+
+/// Parse a Retry-After value: delay-seconds or an IMF-fixdate HTTP-date
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    // A date in the past means "retry now"
+    let date = parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+/// Parse an IMF-fixdate HTTP-date, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+    let day: i64 = parts[1].parse().ok()?;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter()
+        .position(|m| *m == parts[2])? as i64 + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let time: Vec<i64> = parts[4].split(':').map(|t| t.parse().ok()).collect::<Option<_>>()?;
+    if time.len() != 3 {
+        return None;
+    }
+
+    let timestamp = days_from_civil(year, month, day) * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    let offset = Duration::from_secs(timestamp.unsigned_abs());
+    if timestamp >= 0 {
+        UNIX_EPOCH.checked_add(offset)
+    } else {
+        UNIX_EPOCH.checked_sub(offset)
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DnsFailure;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(784111777); // Sun, 06 Nov 1994 08:49:37 GMT
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:50:07 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Sat, 05 Nov 1994 08:49:37 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_deterministic_backoff() {
+        let policy = RetryPolicy::new();
+        let delays = |policy: &RetryPolicy| {
+            let mut rng = policy.jitter_rng(true);
+            (0..6).map(|attempt| policy.backoff(attempt, &mut rng)).collect::<Vec<_>>()
+        };
+
+        let first = delays(&policy);
+        assert_eq!(first, delays(&policy));
+        for (attempt, delay) in first.iter().enumerate() {
+            let exponential = (Duration::from_millis(100) * 2u32.pow(attempt as u32)).min(Duration::from_secs(10));
+            assert!(*delay >= exponential / 2 && *delay <= exponential);
+        }
+    }
+
+    #[test]
+    fn test_idempotency_rules() {
+        let policy = RetryPolicy::new();
+        let reset = Error::from(ErrorKind::ConnectionReset);
+        let refused = Error::from(ErrorKind::ConnectionRefused);
+
+        assert!(policy.should_retry_error("GET", &reset));
+        assert!(!policy.should_retry_error("POST", &reset));
+        assert!(policy.should_retry_error("POST", &refused));
+
+        // Typed errors follow HttpError::is_transient
+        let servfail = HttpError::dns("flaky.example", DnsFailure::ServerFailure, None);
+        assert!(policy.should_retry_error("POST", &servfail));
+        let proxy_denied = HttpError::proxy("proxy:3128", Error::new(ErrorKind::PermissionDenied, "Proxy authentication required"));
+        assert!(!policy.should_retry_error("GET", &proxy_denied));
+        let timeout = HttpError::timeout("Read timed out");
+        assert!(policy.should_retry_error("GET", &timeout) && !policy.should_retry_error("POST", &timeout));
+        let reset_while_connecting = HttpError::connect("127.0.0.1:9", Error::from(ErrorKind::ConnectionReset));
+        assert!(policy.should_retry_error("POST", &reset_while_connecting));
+        assert!(policy.should_retry_status("PUT", 503));
+        assert!(!policy.should_retry_status("POST", 503));
+        assert!(policy.should_retry_status("POST", 429));
+        assert!(!policy.should_retry_status("GET", 500));
+    }
+}
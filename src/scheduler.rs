@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Error, Result};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Semaphore, oneshot};
+use tokio::task::JoinSet;
+
+use crate::http_client::{HttpClient, HttpRequest, HttpResponse, MachineHttpPriority};
+
+/// Queue for a priority: Latency first, then unprioritized and Accuracy, then Throughput
+fn queue_index(priority: Option<MachineHttpPriority>) -> usize {
+    match priority {
+        Some(MachineHttpPriority::Latency) => 0,
+        None | Some(MachineHttpPriority::Accuracy) => 1,
+        Some(MachineHttpPriority::Throughput) => 2,
+    }
+}
+
+struct PermitState {
+    available: usize,
+    /// Waiters per queue, each woken by handing it a released permit
+    waiting: [VecDeque<oneshot::Sender<()>>; 3],
+}
+
+/// Connection permits handed out by request priority
+///
+/// Waiters queue separately per priority. A released permit goes to the
+/// oldest waiter of the most urgent non-empty queue, so Latency requests
+/// jump ahead of queued Throughput work. Within a queue order is FIFO.
+pub struct PriorityPermits {
+    state: Mutex<PermitState>,
+}
+
+impl PriorityPermits {
+    pub fn new(permits: usize) -> Self {
+        PriorityPermits {
+            state: Mutex::new(PermitState {
+                available: permits,
+                waiting: Default::default(),
+            }),
+        }
+    }
+
+    /// Wait for a permit in the queue for `priority`
+    pub async fn acquire(&self, priority: Option<MachineHttpPriority>) -> PriorityPermit<'_> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            // Only take a free permit directly if nobody is queued ahead
+            if state.available > 0 && state.waiting.iter().all(VecDeque::is_empty) {
+                state.available -= 1;
+                return PriorityPermit { permits: self };
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiting[queue_index(priority)].push_back(sender);
+            receiver
+        };
+
+        let mut waiter = Waiter { permits: self, receiver: Some(receiver) };
+        if let Some(receiver) = waiter.receiver.as_mut() {
+            // Senders are only dropped after sending, so this cannot fail
+            let _ = receiver.await;
+        }
+        waiter.receiver = None;
+        PriorityPermit { permits: self }
+    }
+
+    /// Permits not currently held
+    pub fn available(&self) -> usize {
+        self.state.lock().unwrap().available
+    }
+
+    /// Requests waiting for a permit, per queue from most to least urgent
+    pub fn queued(&self) -> [usize; 3] {
+        let state = self.state.lock().unwrap();
+        [state.waiting[0].len(), state.waiting[1].len(), state.waiting[2].len()]
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        for queue in state.waiting.iter_mut() {
+            while let Some(sender) = queue.pop_front() {
+                // A waiter that gave up has dropped its receiver; try the next one
+                if sender.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+impl fmt::Debug for PriorityPermits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityPermits")
+            .field("available", &self.available())
+            .field("queued", &self.queued())
+            .finish()
+    }
+}
+
+/// A permit from [`PriorityPermits`], returned when dropped
+pub struct PriorityPermit<'a> {
+    permits: &'a PriorityPermits,
+}
+
+impl Drop for PriorityPermit<'_> {
+    fn drop(&mut self) {
+        self.permits.release();
+    }
+}
+
+/// A queued acquire; if cancelled after being handed a permit, passes it on
+struct Waiter<'a> {
+    permits: &'a PriorityPermits,
+    receiver: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if let Some(mut receiver) = self.receiver.take() {
+            receiver.close();
+            if receiver.try_recv().is_ok() {
+                self.permits.release();
+            }
+        }
+    }
+}
+
+/// Execute `requests` concurrently, returning results in request order
+///
+/// Throughput requests are held back so that at most `throughput_limit` of
+/// them are in flight at once; the rest start immediately. Connection
+/// permits are then granted by priority through the pool.
+pub async fn execute_batch(client: &HttpClient, requests: Vec<HttpRequest>, throughput_limit: usize) -> Vec<Result<HttpResponse>> {
+    let throughput_slots = Arc::new(Semaphore::new(throughput_limit.max(1)));
+    let count = requests.len();
+    let mut tasks = JoinSet::new();
+
+    for (index, request) in requests.into_iter().enumerate() {
+        let client = client.clone();
+        let slots = (request.machine_priority() == Some(MachineHttpPriority::Throughput)).then(|| throughput_slots.clone());
+        tasks.spawn(async move {
+            let _slot = match &slots {
+                Some(slots) => Some(slots.acquire().await),
+                None => None,
+            };
+            [(index, client.execute(&request).await)]
+        });
+    }
+    collect_ordered(tasks, count).await
+}
+
+/// Wait for `tasks` and place each `(index, result)` they return, in index order
+///
+/// A panicked task leaves its slots empty; they are reported as errors.
+pub(crate) async fn collect_ordered<I>(mut tasks: JoinSet<I>, count: usize) -> Vec<Result<HttpResponse>>
+where
+    I: IntoIterator<Item = (usize, Result<HttpResponse>)> + 'static,
+{
+    let mut results: Vec<Option<Result<HttpResponse>>> = (0..count).map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        for (index, result) in joined.into_iter().flatten() {
+            results[index] = Some(result);
+        }
+    }
+    results.into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(Error::other("Request task panicked"))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_latency_jumps_queue() {
+        let permits = Arc::new(PriorityPermits::new(1));
+        let held = permits.acquire(None).await;
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let mut tasks = JoinSet::new();
+        for (name, priority) in [("throughput", MachineHttpPriority::Throughput), ("accuracy", MachineHttpPriority::Accuracy), ("latency", MachineHttpPriority::Latency)] {
+            let (task_permits, order) = (permits.clone(), order.clone());
+            tasks.spawn(async move {
+                let _permit = task_permits.acquire(Some(priority)).await;
+                order.lock().unwrap().push(name);
+            });
+            // Queue them in this order
+            while permits.queued().iter().sum::<usize>() < tasks.len() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        assert_eq!(permits.queued(), [1, 1, 1]);
+
+        // A cancelled waiter does not swallow the permit
+        let abandoned = tokio::time::timeout(Duration::from_millis(5), permits.acquire(Some(MachineHttpPriority::Latency))).await;
+        assert!(abandoned.is_err());
+
+        drop(held);
+        while tasks.join_next().await.is_some() {}
+        assert_eq!(*order.lock().unwrap(), ["latency", "accuracy", "throughput"]);
+        assert_eq!(permits.available(), 1);
+    }
+}
@@ -6,8 +6,10 @@ use std::hash::Hash;
 use tokio::time::interval; 
 use tokio::sync::Semaphore; 
 
+use crate::auth::{Authenticator, CredentialStore}; 
 use crate::dom::DomSnapshot; 
 use crate::deterministic::DeterministicControlParams; 
+use crate::http_client::HttpClient; 
 
 /// Session ID type for Machine-HTTP 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)] 
@@ -79,7 +81,7 @@ impl ResourcePool {
     
     /// Acquire a resource from the pool
     pub async fn acquire(&self) -> Option<SharedResource> {
-        let permit = self.semaphore.acquire().await.ok()?;
+        let _permit = self.semaphore.acquire().await.ok()?;
         
         let mut available = self.available.lock().unwrap();
         if let Some(resource) = available.pop() {
@@ -142,8 +144,23 @@ impl Default for SessionMeta {
     }
 }
 
+impl SessionMeta {
+    /// Session creation time (milliseconds since epoch)
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+    
+    /// Number of requests made with this session
+    pub fn request_count(&self) -> u32 {
+        self.request_count
+    }
+}
+
 /// Session state structure with efficient compression
-#[derive(Debug, Clone)]
+///
+/// Cloning is deep: a clone, e.g. a snapshot, gets its own copy of the
+/// credential store.
+#[derive(Debug, Default)]
 pub struct SessionState {
     /// Session metadata
     pub meta: SessionMeta, 
@@ -155,22 +172,25 @@ pub struct SessionState {
     pub headers: HashMap<String, String>, 
     /// Cookies for the session
     pub cookies: HashMap<String, String>, 
+    /// Credentials by origin, used by the client from `Session::http_client`
+    pub credentials: CredentialStore, 
     /// Optional DOM snapshot for the session
     pub dom_snapshot: Option<DomSnapshot>, 
     /// Compressed session data (for efficient storage)
     compressed_data: Option<Vec<u8>>, 
 }
 
-impl Default for SessionState {
-    fn default() -> Self {
+impl Clone for SessionState {
+    fn clone(&self) -> Self {
         SessionState {
-            meta: SessionMeta::default(),
-            deterministic_params: None,
-            current_url: None,
-            headers: HashMap::new(),
-            cookies: HashMap::new(),
-            dom_snapshot: None,
-            compressed_data: None,
+            meta: self.meta.clone(),
+            deterministic_params: self.deterministic_params.clone(),
+            current_url: self.current_url.clone(),
+            headers: self.headers.clone(),
+            cookies: self.cookies.clone(),
+            credentials: self.credentials.deep_clone(),
+            dom_snapshot: self.dom_snapshot.clone(),
+            compressed_data: self.compressed_data.clone(),
         }
     }
 }
@@ -289,17 +309,27 @@ impl Session {
     }
     
     /// Get the session state (read-only)
-    pub fn get_state(&self) -> std::sync::RwLockReadGuard<SessionState> {
+    pub fn get_state(&self) -> std::sync::RwLockReadGuard<'_, SessionState> {
         self.state.read().unwrap()
     }
     
     /// Get mutable access to the session state
-    pub fn get_mut_state(&self) -> std::sync::RwLockWriteGuard<SessionState> {
+    pub fn get_mut_state(&self) -> std::sync::RwLockWriteGuard<'_, SessionState> {
         let mut state = self.state.write().unwrap();
         state.touch();
         state
     }
     
+    /// A copy of `base` that authenticates with the session's credentials
+    ///
+    /// The client shares the session's credential store, so credentials
+    /// added to the session later apply to it as well.
+    pub fn http_client(&self, base: &HttpClient) -> HttpClient {
+        let mut client = base.clone();
+        client.add_middleware(Authenticator::new(self.get_state().credentials.clone()));
+        client
+    }
+    
     /// Acquire a shared resource from the pool
     pub async fn acquire_resource(&self, resource_type: &str) -> Option<SharedResource> {
         self.resource_pools.acquire_resource(resource_type).await
@@ -588,7 +618,7 @@ mod tests {
         
         // Create a session
         let session = session_manager.create_session().unwrap();
-        assert!(session.id.as_str().len() > 0);
+        assert!(!session.id.as_str().is_empty());
         
         // Get the session back
         let retrieved_session = session_manager.get_session(&session.id);
@@ -604,4 +634,25 @@ mod tests {
         assert!(removed);
         assert_eq!(session_manager.total_session_count(), 0);
     }
+    
+    #[tokio::test]
+    async fn test_session_client_uses_session_credentials() {
+        use crate::auth::Credentials;
+        use crate::http_client::HttpRequest;
+        use crate::test_support::{pooled_client, spawn_server};
+        
+        let (port, server) = spawn_server(vec!["HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_string()]);
+        let origin = format!("http://127.0.0.1:{}", port);
+        let session = Session::new(SessionId::new("auth-session"), Arc::new(ResourcePoolManager::new(100)));
+        let client = session.http_client(&pooled_client());
+        
+        // Added after the client was made, and not shared with a snapshot
+        let snapshot = session.get_state().clone();
+        session.get_state().credentials.insert(&origin, Credentials::basic("agent", "secret")).unwrap();
+        assert!(snapshot.credentials.get(&origin).is_none());
+        
+        let response = client.execute(&HttpRequest::new("GET", &format!("{}/private", origin))).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert!(server.join().unwrap()[0].contains("Authorization: Basic YWdlbnQ6c2VjcmV0\r\n"));
+    }
 }
@@ -0,0 +1,366 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+use crate::http_client::{HttpClient, HttpRequest, ResponseBody};
+
+/// Reconnection delay until the server sends a `retry` field
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(3);
+
+/// One dispatched Server-Sent Event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Event type, "message" unless the server named one
+    pub event: String,
+    /// Data lines joined with '\n'
+    pub data: String,
+    /// Last event ID in effect when this event was dispatched
+    pub id: Option<String>,
+}
+
+/// Incremental `text/event-stream` parser
+///
+/// Bytes can be fed in arbitrary pieces; lines may end in CRLF, LF or CR,
+/// and multi-byte characters may be split across pieces.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    line: Vec<u8>,
+    /// The previous piece ended in CR, so a leading LF belongs to that line
+    after_cr: bool,
+    started: bool,
+    event: String,
+    data: String,
+    has_data: bool,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the next piece of the stream, returning every event it completes
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+
+        for &byte in bytes {
+            if self.after_cr {
+                self.after_cr = false;
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    let line = std::mem::take(&mut self.line);
+                    if let Some(event) = self.process_line(&line) {
+                        events.push(event);
+                    }
+                }
+                _ => self.line.push(byte),
+            }
+        }
+
+        events
+    }
+
+    /// ID to send as `Last-Event-ID` when reconnecting
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// Reconnection time requested by the server's latest `retry` field
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Drop a partially received event, e.g. after the connection broke
+    pub fn reset_event(&mut self) {
+        self.line.clear();
+        self.after_cr = false;
+        self.event.clear();
+        self.data.clear();
+        self.has_data = false;
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        let mut line = String::from_utf8_lossy(line);
+        if !self.started {
+            self.started = true;
+            if let Some(stripped) = line.strip_prefix('\u{feff}') {
+                line = stripped.to_string().into();
+            }
+        }
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(millis) = value.parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = std::mem::take(&mut self.event);
+        let data = std::mem::take(&mut self.data);
+        if !std::mem::take(&mut self.has_data) {
+            return None;
+        }
+        Some(SseEvent {
+            event: if event.is_empty() { "message".to_string() } else { event },
+            data,
+            id: self.last_event_id.clone(),
+        })
+    }
+}
+
+/// Server-Sent Events client built on [`HttpClient::execute_stream`]
+///
+/// Events are yielded as they arrive. When the stream ends or breaks, the
+/// request is sent again after the reconnection delay with `Last-Event-ID`
+/// set, until the server answers 204 No Content.
+pub struct EventSource<'a> {
+    client: &'a HttpClient,
+    request: HttpRequest,
+    parser: SseParser,
+    body: Option<ResponseBody<'a>>,
+    pending: VecDeque<SseEvent>,
+    reconnect_delay: Duration,
+    idle_timeout: Option<Duration>,
+    max_reconnects: Option<u32>,
+    failed_reconnects: u32,
+    connected: bool,
+    closed: bool,
+}
+
+impl<'a> EventSource<'a> {
+    pub fn new(client: &'a HttpClient, request: HttpRequest) -> Self {
+        EventSource {
+            client,
+            request,
+            parser: SseParser::new(),
+            body: None,
+            pending: VecDeque::new(),
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            idle_timeout: None,
+            max_reconnects: None,
+            failed_reconnects: 0,
+            connected: false,
+            closed: false,
+        }
+    }
+
+    /// Delay before reconnecting, until the server sends a `retry` field (default 3s)
+    pub fn set_reconnect_delay(&mut self, delay: Duration) -> &mut Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Reconnect when the stream sends nothing for this long (default: wait indefinitely)
+    ///
+    /// The client's read timeout does not apply to the event stream, since a
+    /// healthy stream may be quiet for a long time.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up after this many consecutive failed reconnection attempts (default: never)
+    pub fn set_max_reconnects(&mut self, max_reconnects: u32) -> &mut Self {
+        self.max_reconnects = Some(max_reconnects);
+        self
+    }
+
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.parser.last_event_id()
+    }
+
+    /// Stop the stream; `next_event` returns `None` afterwards
+    pub fn close(&mut self) {
+        self.body = None;
+        self.closed = true;
+    }
+
+    /// Next event, or `None` once the server ends the stream with 204
+    ///
+    /// A non-200 status or wrong Content-Type is returned as an error and
+    /// closes the stream, as does exhausting the reconnection attempts.
+    pub async fn next_event(&mut self) -> Option<Result<SseEvent>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.closed {
+                return None;
+            }
+
+            let Some(body) = self.body.as_mut() else {
+                if self.connected {
+                    tokio::time::sleep(self.parser.retry().unwrap_or(self.reconnect_delay)).await;
+                }
+                match self.connect().await {
+                    Ok(true) => continue,
+                    Ok(false) => {
+                        self.closed = true;
+                        return None;
+                    }
+                    Err(e) => {
+                        let retryable = e.kind() != ErrorKind::InvalidData;
+                        self.failed_reconnects += 1;
+                        let exhausted = self.max_reconnects.is_some_and(|max| self.failed_reconnects > max);
+                        if !self.connected || !retryable || exhausted {
+                            self.closed = true;
+                            return Some(Err(e));
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            match body.next_chunk().await {
+                Some(Ok(chunk)) => self.pending.extend(self.parser.feed(&chunk)),
+                // End of stream or a broken connection: reconnect
+                Some(Err(_)) | None => {
+                    self.body = None;
+                    self.parser.reset_event();
+                }
+            }
+        }
+    }
+
+    /// Open the stream; `Ok(false)` means the server asked us to stop
+    async fn connect(&mut self) -> Result<bool> {
+        let mut request = self.request.clone();
+        request.set_header("Accept", "text/event-stream");
+        request.set_header("Cache-Control", "no-cache");
+        // An empty id field resets the last event ID, so there is nothing to send
+        if let Some(id) = self.parser.last_event_id().filter(|id| !id.is_empty()) {
+            request.set_header("Last-Event-ID", id);
+        }
+
+        let response = self.client.execute_stream(&request).await?;
+        self.connected = true;
+        if response.head.status == 204 {
+            return Ok(false);
+        }
+        if response.head.status != 200 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Event stream returned status {}", response.head.status)));
+        }
+        let content_type = response.head.header("content-type").unwrap_or("");
+        if !content_type.to_ascii_lowercase().starts_with("text/event-stream") {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unexpected event stream Content-Type: {}", content_type)));
+        }
+
+        self.failed_reconnects = 0;
+        let mut body = response.body;
+        body.set_timeout(self.idle_timeout);
+        self.body = Some(body);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{listen, pooled_client, read_head, spawn_server};
+    use std::io::{BufReader, Write};
+
+    #[test]
+    fn test_parse_split_events() {
+        let mut parser = SseParser::new();
+        let mut events = parser.feed(b"\xEF\xBB\xBFretry: 250\r\n: comment\r\nevent: tick\r\ndata: caf\xC3");
+        assert!(events.is_empty());
+        events.extend(parser.feed(b"\xA9\r"));
+        events.extend(parser.feed(b"\ndata:second line\rid: 7\r\n\r\ndata\n\nid\n\n"));
+
+        assert_eq!(events, [
+            SseEvent { event: "tick".to_string(), data: "café\nsecond line".to_string(), id: Some("7".to_string()) },
+            SseEvent { event: "message".to_string(), data: String::new(), id: Some("7".to_string()) },
+        ]);
+        assert_eq!(parser.retry(), Some(Duration::from_millis(250)));
+        // An empty id field resets the last event ID
+        assert_eq!(parser.last_event_id(), Some(""));
+    }
+
+    #[tokio::test]
+    async fn test_event_source_reconnects_with_last_event_id() {
+        let (port, server) = spawn_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n1d\r\nretry: 10\nid: 1\ndata: hello\n\n\r\n0\r\n\r\n".to_string(),
+            "HTTP/1.1 204 No Content\r\n\r\n".to_string(),
+        ]);
+
+        let client = pooled_client();
+        let mut events = client.event_source(HttpRequest::new("GET", &format!("http://127.0.0.1:{}/stream", port)));
+
+        let event = events.next_event().await.unwrap().unwrap();
+        assert_eq!((event.event.as_str(), event.data.as_str(), event.id.as_deref()), ("message", "hello", Some("1")));
+        // The stream ended, so the client reconnects and the server answers 204
+        assert!(events.next_event().await.is_none());
+
+        let heads = server.join().unwrap();
+        assert!(heads[0].contains("Accept: text/event-stream"));
+        assert!(heads[1].contains("Last-Event-ID: 1"));
+    }
+
+    #[tokio::test]
+    async fn test_quiet_stream_outlives_read_timeout() {
+        let (listener, port) = listen();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut heads = Vec::new();
+            let chunk = |data: &str| format!("{:x}\r\n{}\r\n", data.len(), data);
+            for step in 0..2 {
+                heads.push(read_head(&mut reader));
+                if step == 1 {
+                    reader.get_mut().write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+                    break;
+                }
+                let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n";
+                reader.get_mut().write_all(format!("{}{}", head, chunk("id: 1\ndata: a\n\n")).as_bytes()).unwrap();
+                // Stay quiet for longer than the client's read timeout
+                std::thread::sleep(Duration::from_millis(300));
+                reader.get_mut().write_all(format!("{}0\r\n\r\n", chunk("id\ndata: b\n\n")).as_bytes()).unwrap();
+            }
+            heads
+        });
+
+        let mut client = pooled_client();
+        client.set_timeout(Duration::from_millis(100));
+        let mut events = client.event_source(HttpRequest::new("GET", &format!("http://127.0.0.1:{}/stream", port)));
+        events.set_reconnect_delay(Duration::from_millis(10)).set_max_reconnects(0);
+
+        assert_eq!(events.next_event().await.unwrap().unwrap().data, "a");
+        assert_eq!(events.next_event().await.unwrap().unwrap().data, "b");
+        assert!(events.next_event().await.is_none());
+
+        // The empty id field cleared the last event ID, so none is sent
+        let heads = server.join().unwrap();
+        assert!(!heads[1].contains("Last-Event-ID"));
+    }
+}
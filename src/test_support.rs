@@ -0,0 +1,82 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::connection_pool::ConnectionPool;
+use crate::dns::DnsResolver;
+use crate::http_client::HttpClient;
+
+/// A listener on an ephemeral local port
+pub fn listen() -> (TcpListener, u16) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (listener, port)
+}
+
+/// Read one request head, including its blank line; empty if the peer closed first
+pub fn read_head(reader: &mut impl BufRead) -> String {
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        if reader.read_line(&mut head).unwrap_or(0) == 0 {
+            return String::new();
+        }
+    }
+    head
+}
+
+/// Read one request head and its Content-Length body
+pub fn read_request(reader: &mut impl BufRead) -> String {
+    let mut request = read_head(reader);
+    let length = request.lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    request.push_str(&String::from_utf8(body).unwrap());
+    request
+}
+
+/// Serve `responses` in order on a single connection, returning the request heads
+pub fn spawn_server(responses: Vec<String>) -> (u16, JoinHandle<Vec<String>>) {
+    let (listener, port) = listen();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut heads = Vec::new();
+        for response in responses {
+            heads.push(read_head(&mut reader));
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+        heads
+    });
+    (port, server)
+}
+
+/// Answer one request per connection, returning each request's head and body
+pub fn spawn_server_per_request(responses: Vec<String>) -> (u16, JoinHandle<Vec<String>>) {
+    let (listener, port) = listen();
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            requests.push(read_request(&mut reader));
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+        requests
+    });
+    (port, server)
+}
+
+/// A fresh connection pool, for tests that share one between clients
+pub fn pool() -> Arc<ConnectionPool> {
+    Arc::new(ConnectionPool::new(HttpClient::new(), DnsResolver::new("8.8.8.8").unwrap()))
+}
+
+/// A client with a pool of its own, so tests do not share connections
+pub fn pooled_client() -> HttpClient {
+    let mut client = HttpClient::new();
+    client.set_connection_pool(pool());
+    client
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use std::future::Future;
+use std::io::{Error, Result};
+use std::time::{Duration, Instant};
+
+use tokio::time::timeout;
+
+use crate::error::{HttpError, find_cause};
+
+/// A stage of a request with its own deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Resolving the target (and proxy) host names
+    Dns,
+    /// Opening the TCP connection, including any proxy tunnel
+    Connect,
+    /// TLS handshake and HTTP/2 connection preface
+    Tls,
+    /// From sending the request until the response head arrives
+    FirstByte,
+    /// The whole request, across redirects and retries
+    Total,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Dns => "DNS lookup",
+            Phase::Connect => "Connect",
+            Phase::Tls => "TLS handshake",
+            Phase::FirstByte => "Time to first byte",
+            Phase::Total => "Request",
+        })
+    }
+}
+
+/// A request phase ran past its deadline
+///
+/// Carried inside `std::io::Error` (kind `TimedOut`) as
+/// [`HttpError::Timeout`]; recover it with [`PhaseTimeout::find`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimeout {
+    pub phase: Phase,
+    pub limit: Duration,
+}
+
+impl PhaseTimeout {
+    pub fn error(phase: Phase, limit: Duration) -> Error {
+        HttpError::timeout(PhaseTimeout { phase, limit })
+    }
+
+    /// The `PhaseTimeout` inside an I/O error, if that is what it wraps
+    pub fn find(error: &Error) -> Option<&PhaseTimeout> {
+        find_cause(error)
+    }
+}
+
+impl fmt::Display for PhaseTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {:?}", self.phase, self.limit)
+    }
+}
+
+impl std::error::Error for PhaseTimeout {}
+
+/// Per-phase deadlines for `HttpClient::execute`
+///
+/// Unset phases are only bounded by the client's read/write timeout and
+/// the pool's connection timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    dns: Option<Duration>,
+    connect: Option<Duration>,
+    tls: Option<Duration>,
+    first_byte: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_dns(&mut self, limit: Duration) -> &mut Self {
+        self.dns = Some(limit);
+        self
+    }
+
+    pub fn set_connect(&mut self, limit: Duration) -> &mut Self {
+        self.connect = Some(limit);
+        self
+    }
+
+    pub fn set_tls(&mut self, limit: Duration) -> &mut Self {
+        self.tls = Some(limit);
+        self
+    }
+
+    pub fn set_first_byte(&mut self, limit: Duration) -> &mut Self {
+        self.first_byte = Some(limit);
+        self
+    }
+
+    pub fn set_total(&mut self, limit: Duration) -> &mut Self {
+        self.total = Some(limit);
+        self
+    }
+
+    /// Deadline for a phase, if one is set
+    pub fn get(&self, phase: Phase) -> Option<Duration> {
+        match phase {
+            Phase::Dns => self.dns,
+            Phase::Connect => self.connect,
+            Phase::Tls => self.tls,
+            Phase::FirstByte => self.first_byte,
+            Phase::Total => self.total,
+        }
+    }
+
+    /// Run `future` as `phase`, failing with [`PhaseTimeout`] past its deadline
+    pub async fn run<T>(&self, phase: Phase, future: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.get(phase) {
+            Some(limit) => timeout(limit, future).await.map_err(|_| PhaseTimeout::error(phase, limit))?,
+            None => future.await,
+        }
+    }
+
+    /// Like [`Timeouts::run`], adding the time spent to `spent`
+    pub async fn timed<T>(&self, phase: Phase, spent: &mut Duration, future: impl Future<Output = Result<T>>) -> Result<T> {
+        let started = Instant::now();
+        let result = self.run(phase, future).await;
+        *spent += started.elapsed();
+        result
+    }
+}
+
+/// Where the time of a request went
+///
+/// Phases that did not happen, such as connecting on a reused connection,
+/// are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub dns: Duration,
+    pub connect: Duration,
+    pub tls: Duration,
+    /// From sending the request until the response head arrived
+    pub first_byte: Duration,
+    /// Reading the body after the head
+    pub download: Duration,
+}
+
+impl Timings {
+    /// Sum of all phases
+    pub fn total(&self) -> Duration {
+        self.dns + self.connect + self.tls + self.first_byte + self.download
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[tokio::test]
+    async fn test_phase_deadline() {
+        let mut timeouts = Timeouts::new();
+        timeouts.set_first_byte(Duration::from_millis(20));
+
+        let mut spent = Duration::ZERO;
+        let error = timeouts.timed(Phase::FirstByte, &mut spent, async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }).await.unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(PhaseTimeout::find(&error), Some(&PhaseTimeout { phase: Phase::FirstByte, limit: Duration::from_millis(20) }));
+        assert!(spent >= Duration::from_millis(20));
+
+        // Phases without a deadline run to completion
+        assert_eq!(timeouts.run(Phase::Dns, async { Ok(7) }).await.unwrap(), 7);
+    }
+}
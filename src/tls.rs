@@ -0,0 +1,185 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+
+use native_tls::{Certificate, Identity, Protocol, TlsConnector};
+use sha2::{Digest, Sha256};
+
+/// How `HttpClient` sets up and verifies TLS connections
+///
+/// The default trusts the system roots and verifies hostnames. Pins are
+/// checked in addition to normal verification, against the SHA-256
+/// fingerprint of the server's leaf certificate.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    root_certificates: Vec<Certificate>,
+    disable_built_in_roots: bool,
+    identity: Option<Identity>,
+    pins: Vec<[u8; 32]>,
+    insecure: bool,
+    min_protocol: Option<Protocol>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust every certificate in a PEM bundle (e.g. an internal CA)
+    pub fn add_root_certificates_pem(&mut self, pem: &[u8]) -> Result<&mut Self> {
+        const END: &str = "-----END CERTIFICATE-----";
+        let text = std::str::from_utf8(pem)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "PEM data is not valid UTF-8"))?;
+
+        let mut certificates = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+            let end = rest[start..].find(END)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Unterminated PEM certificate"))? + start + END.len();
+            certificates.push(Certificate::from_pem(&rest.as_bytes()[start..end]).map_err(tls_error)?);
+            rest = &rest[end..];
+        }
+        if certificates.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "No certificates found in PEM data"));
+        }
+        self.root_certificates.extend(certificates);
+        Ok(self)
+    }
+
+    /// Trust a DER-encoded certificate
+    pub fn add_root_certificate_der(&mut self, der: &[u8]) -> Result<&mut Self> {
+        self.root_certificates.push(Certificate::from_der(der).map_err(tls_error)?);
+        Ok(self)
+    }
+
+    /// Trust only the added roots, not the system store
+    pub fn set_built_in_roots(&mut self, enabled: bool) -> &mut Self {
+        self.disable_built_in_roots = !enabled;
+        self
+    }
+
+    /// Present a client certificate from a PKCS #12 archive (mTLS)
+    pub fn set_client_identity_pkcs12(&mut self, der: &[u8], password: &str) -> Result<&mut Self> {
+        self.identity = Some(Identity::from_pkcs12(der, password).map_err(tls_error)?);
+        Ok(self)
+    }
+
+    /// Present a client certificate chain and PKCS #8 private key, both PEM (mTLS)
+    pub fn set_client_identity_pem(&mut self, certificate_pem: &[u8], key_pem: &[u8]) -> Result<&mut Self> {
+        self.identity = Some(Identity::from_pkcs8(certificate_pem, key_pem).map_err(tls_error)?);
+        Ok(self)
+    }
+
+    /// Require the leaf certificate to have this SHA-256 fingerprint
+    ///
+    /// Takes hex, optionally colon-separated as printed by
+    /// `openssl x509 -fingerprint -sha256`. With several pins any one may match.
+    pub fn add_pin_sha256(&mut self, fingerprint: &str) -> Result<&mut Self> {
+        let hex: String = fingerprint.chars().filter(|c| *c != ':').collect();
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid SHA-256 fingerprint: {}", fingerprint));
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut pin = [0u8; 32];
+        for (byte, pair) in pin.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        self.pins.push(pin);
+        Ok(self)
+    }
+
+    /// Skip certificate and hostname verification (local test servers only)
+    ///
+    /// Pins are still enforced, so pinning a self-signed certificate together
+    /// with insecure mode trusts exactly that certificate.
+    pub fn set_insecure(&mut self, enabled: bool) -> &mut Self {
+        self.insecure = enabled;
+        self
+    }
+
+    /// Refuse protocol versions older than `protocol`
+    pub fn set_min_protocol(&mut self, protocol: Protocol) -> &mut Self {
+        self.min_protocol = Some(protocol);
+        self
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    /// Build a connector offering `alpn` protocols (none if empty)
+    pub fn connector(&self, alpn: &[&str]) -> Result<TlsConnector> {
+        let mut builder = TlsConnector::builder();
+        for certificate in &self.root_certificates {
+            builder.add_root_certificate(certificate.clone());
+        }
+        builder
+            .disable_built_in_roots(self.disable_built_in_roots)
+            .danger_accept_invalid_certs(self.insecure)
+            .danger_accept_invalid_hostnames(self.insecure)
+            .min_protocol_version(self.min_protocol);
+        if let Some(identity) = &self.identity {
+            builder.identity(identity.clone());
+        }
+        if !alpn.is_empty() {
+            builder.request_alpns(alpn);
+        }
+        builder.build().map_err(tls_error)
+    }
+
+    /// Check a DER-encoded leaf certificate against the configured pins
+    pub fn verify_pin(&self, der: Option<&[u8]>) -> Result<()> {
+        if self.pins.is_empty() {
+            return Ok(());
+        }
+        let fingerprint = der.map(fingerprint_sha256);
+        if fingerprint.is_some_and(|fingerprint| self.pins.contains(&fingerprint)) {
+            return Ok(());
+        }
+        Err(Error::new(ErrorKind::PermissionDenied, "Server certificate does not match any pinned fingerprint"))
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Identities hold private keys, so only report whether one is set
+        f.debug_struct("TlsConfig")
+            .field("root_certificates", &self.root_certificates.len())
+            .field("disable_built_in_roots", &self.disable_built_in_roots)
+            .field("client_identity", &self.identity.is_some())
+            .field("pins", &self.pins.len())
+            .field("insecure", &self.insecure)
+            .finish()
+    }
+}
+
+/// SHA-256 fingerprint of a DER-encoded certificate
+pub fn fingerprint_sha256(der: &[u8]) -> [u8; 32] {
+    Sha256::digest(der).into()
+}
+
+fn tls_error(error: native_tls::Error) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("TLS configuration error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_verification() {
+        // SHA-256("abc")
+        let fingerprint = "BA:78:16:BF:8F:01:CF:EA:41:41:40:DE:5D:AE:22:23:B0:03:61:A3:96:17:7A:9C:B4:10:FF:61:F2:00:15:AD";
+        let mut config = TlsConfig::new();
+        assert!(config.verify_pin(None).is_ok());
+
+        config.add_pin_sha256(fingerprint).unwrap();
+        assert!(config.verify_pin(Some(b"abc")).is_ok());
+        assert_eq!(config.verify_pin(Some(b"abd")).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(config.verify_pin(None).is_err());
+
+        assert!(config.add_pin_sha256("not-a-fingerprint").is_err());
+        assert!(config.add_root_certificates_pem(b"garbage").is_err());
+    }
+}
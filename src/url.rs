@@ -0,0 +1,312 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+
+/// Parsed absolute URL (scheme, authority, path and query)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub scheme: String,
+    pub userinfo: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// Percent-encoded path, always starting with `/`
+    pub path: String,
+    /// Percent-encoded query without the leading `?`
+    pub query: Option<String>,
+}
+
+impl Url {
+    /// Parse an absolute URL, percent-encoding the path and query as needed
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let (scheme, rest) = input.split_once("://")
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Not an absolute URL: {}", input)))?;
+
+        let scheme = scheme.to_ascii_lowercase();
+        if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid URL scheme: {}", scheme)));
+        }
+
+        // Drop the fragment; it is never sent to the server
+        let rest = rest.split('#').next().unwrap_or("");
+
+        let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path_and_query) = rest.split_at(authority_end);
+
+        let (userinfo, host_port) = match authority.rsplit_once('@') {
+            Some((userinfo, host_port)) => (Some(userinfo.to_string()), host_port),
+            None => (None, authority),
+        };
+
+        let (host, port) = Self::split_host_port(host_port)?;
+        let port = match port {
+            Some(port) => port,
+            None => Self::default_port(&scheme)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("No default port for scheme: {}", scheme)))?,
+        };
+
+        let (path, query) = match path_and_query.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path_and_query, None),
+        };
+        let path = if path.is_empty() { "/".to_string() } else { percent_encode(path, PATH_SAFE) };
+        let query = query.map(|q| percent_encode(q, QUERY_SAFE));
+
+        Ok(Url {
+            scheme,
+            userinfo,
+            host,
+            port,
+            path,
+            query,
+        })
+    }
+
+    /// Well-known port for a scheme
+    pub fn default_port(scheme: &str) -> Option<u16> {
+        match scheme {
+            "http" | "ws" => Some(80),
+            "https" | "wss" => Some(443),
+            _ => None,
+        }
+    }
+
+    /// Path plus query, as sent on the request line
+    pub fn request_target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+
+    /// Value for the Host header (port omitted when it is the default)
+    pub fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if Self::default_port(&self.scheme) == Some(self.port) {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    /// Resolve a (possibly relative) reference against this URL (RFC 3986 §5.2)
+    pub fn join(&self, reference: &str) -> Result<Url> {
+        let reference = reference.trim();
+        let reference = reference.split('#').next().unwrap_or("");
+
+        // Absolute references carry their own scheme
+        let scheme_end = reference.find([':', '/', '?']);
+        if let Some(end) = scheme_end
+            && reference[end..].starts_with("://")
+        {
+            return Url::parse(reference);
+        }
+        if let Some(network_path) = reference.strip_prefix("//") {
+            return Url::parse(&format!("{}://{}", self.scheme, network_path));
+        }
+
+        let (path, query) = match reference.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (reference, None),
+        };
+
+        let (path, query) = if path.is_empty() {
+            (self.path.clone(), query.map(str::to_string).or_else(|| self.query.clone()))
+        } else if path.starts_with('/') {
+            (remove_dot_segments(path), query.map(str::to_string))
+        } else {
+            let base_dir = match self.path.rfind('/') {
+                Some(slash) => &self.path[..=slash],
+                None => "/",
+            };
+            (remove_dot_segments(&format!("{}{}", base_dir, path)), query.map(str::to_string))
+        };
+
+        Ok(Url {
+            scheme: self.scheme.clone(),
+            userinfo: self.userinfo.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            path: percent_encode(&path, PATH_SAFE),
+            query: query.map(|q| percent_encode(&q, QUERY_SAFE)),
+        })
+    }
+
+    /// Whether both URLs share scheme, host and port
+    pub fn same_origin(&self, other: &Url) -> bool {
+        self.scheme == other.scheme && self.host == other.host && self.port == other.port
+    }
+
+    fn split_host_port(host_port: &str) -> Result<(String, Option<u16>)> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid URL host: {}", host_port));
+
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            // IPv6 literal, e.g. [::1]:8080
+            let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+            let port = match rest {
+                "" => None,
+                rest => Some(rest.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (host, port)
+        } else {
+            match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        let port = match port {
+            Some("") | None => None,
+            Some(port) => Some(port.parse::<u16>().map_err(|_| invalid())?),
+        };
+
+        Ok((host.to_ascii_lowercase(), port))
+    }
+}
+
+/// The URL without its userinfo, so credentials never reach logs or redirect history
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.host_header(), self.request_target())
+    }
+}
+
+/// Remove `.` and `..` segments from an absolute path
+fn remove_dot_segments(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    let mut output: Vec<&str> = Vec::new();
+
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." => {}
+            ".." => {
+                output.pop();
+            }
+            segment => {
+                output.push(segment);
+                continue;
+            }
+        }
+        // A trailing dot segment still refers to a directory
+        if last {
+            output.push("");
+        }
+    }
+
+    format!("/{}", output.join("/"))
+}
+
+/// Characters left as-is in a path (besides unreserved characters)
+const PATH_SAFE: &str = "/:@!$&'()*+,;=";
+/// Characters left as-is in a query (besides unreserved characters)
+const QUERY_SAFE: &str = "/?:@!$&'()*+,;=";
+
+/// Percent-encode everything except unreserved characters, `safe` and
+/// existing `%XX` escapes
+pub fn percent_encode(input: &str, safe: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut encoded = String::with_capacity(input.len());
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        let is_escape = byte == b'%'
+            && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+            && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit);
+
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) || safe.as_bytes().contains(&byte) || is_escape {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+/// Decode `%XX` escapes (invalid escapes are kept verbatim)
+pub fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            if let (Some(high), Some(low)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_components() {
+        let url = Url::parse("HTTPS://API.Example.com:8443/v1/items?page=2&q=a b#frag").unwrap();
+
+        assert_eq!(url.scheme, "https");
+        assert_eq!(url.host, "api.example.com");
+        assert_eq!(url.port, 8443);
+        assert_eq!(url.path, "/v1/items");
+        assert_eq!(url.query.as_deref(), Some("page=2&q=a%20b"));
+        assert_eq!(url.host_header(), "api.example.com:8443");
+        assert_eq!(url.request_target(), "/v1/items?page=2&q=a%20b");
+    }
+
+    #[test]
+    fn test_defaults_and_ipv6() {
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!(url.port, 80);
+        assert_eq!(url.request_target(), "/");
+        assert_eq!(url.host_header(), "example.com");
+
+        let url = Url::parse("http://user:pw@[::1]:8080/caf\u{e9}").unwrap();
+        assert_eq!(url.userinfo.as_deref(), Some("user:pw"));
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/caf%C3%A9");
+        assert_eq!(url.host_header(), "[::1]:8080");
+        assert_eq!(url.to_string(), "http://[::1]:8080/caf%C3%A9");
+    }
+
+    #[test]
+    fn test_percent_encoding_round_trip() {
+        assert_eq!(percent_encode("/a%20b/100%", PATH_SAFE), "/a%20b/100%25");
+        assert_eq!(percent_decode("a%20b%zz%C3%A9"), "a b%zz\u{e9}".as_bytes());
+    }
+
+    #[test]
+    fn test_join_references() {
+        let base = Url::parse("https://example.com/a/b/c?x=1").unwrap();
+
+        assert_eq!(base.join("d").unwrap().to_string(), "https://example.com/a/b/d");
+        assert_eq!(base.join("../d?y=2").unwrap().to_string(), "https://example.com/a/d?y=2");
+        assert_eq!(base.join("/x/./y/..").unwrap().to_string(), "https://example.com/x/");
+        assert_eq!(base.join("?z").unwrap().to_string(), "https://example.com/a/b/c?z");
+        assert_eq!(base.join("").unwrap().to_string(), "https://example.com/a/b/c?x=1");
+        assert_eq!(base.join("//other.org/p").unwrap().to_string(), "https://other.org/p");
+        assert_eq!(base.join("http://other.org:8080").unwrap().to_string(), "http://other.org:8080/");
+
+        assert!(base.same_origin(&base.join("/elsewhere").unwrap()));
+        assert!(!base.same_origin(&Url::parse("http://example.com/a").unwrap()));
+    }
+
+    #[test]
+    fn test_rejects_relative_and_unknown_port() {
+        assert!(Url::parse("/relative/path").is_err());
+        assert!(Url::parse("ftp://example.com/").is_err());
+        assert!(Url::parse("http://example.com:99999/").is_err());
+    }
+}
@@ -0,0 +1,685 @@
+use std::io::{BufRead, Error, ErrorKind, Read, Result, Write};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use sha1::{Digest, Sha1};
+
+use crate::http1::{self, ResponseDecoder};
+use crate::http_client::HttpRequest;
+
+/// Appended to the key before hashing it into Sec-WebSocket-Accept (RFC 6455 §1.3)
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Trailer of a sync flush, stripped from compressed messages (RFC 7692 §7.2.1)
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+/// Control frames carry at most 125 bytes (RFC 6455 §5.5)
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// Close codes sent when the peer breaks the protocol
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Result<Self> {
+        Ok(match bits {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unknown WebSocket opcode {:#x}", bits))),
+        })
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// A single WebSocket frame with its payload unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    /// RSV1, set on the first frame of a compressed message
+    pub compressed: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Serialize the frame, masking the payload with `mask` if given (clients must mask)
+    pub fn encode(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.payload.len() + 14);
+        out.push((self.fin as u8) << 7 | (self.compressed as u8) << 6 | self.opcode.bits());
+
+        let mask_bit = (mask.is_some() as u8) << 7;
+        let len = self.payload.len();
+        if len < 126 {
+            out.push(mask_bit | len as u8);
+        } else if len <= u16::MAX as usize {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        match mask {
+            Some(mask) => {
+                out.extend_from_slice(&mask);
+                out.extend(self.payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+            }
+            None => out.extend_from_slice(&self.payload),
+        }
+        out
+    }
+
+    /// Read one frame, requiring the payload to be masked or unmasked as given
+    ///
+    /// Payloads over `max_size` bytes are rejected before being read.
+    pub fn read<R: Read>(reader: &mut R, max_size: usize, expect_masked: bool) -> Result<Frame> {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+
+        if header[0] & 0x30 != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Unexpected RSV2/RSV3 bits in WebSocket frame"));
+        }
+        let fin = header[0] & 0x80 != 0;
+        let compressed = header[0] & 0x40 != 0;
+        let opcode = Opcode::from_bits(header[0] & 0x0F)?;
+        let masked = header[1] & 0x80 != 0;
+        if masked != expect_masked {
+            return Err(Error::new(ErrorKind::InvalidData, "WebSocket frame masking does not match the peer's role"));
+        }
+
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut bytes = [0u8; 2];
+                reader.read_exact(&mut bytes)?;
+                u16::from_be_bytes(bytes) as u64
+            }
+            127 => {
+                let mut bytes = [0u8; 8];
+                reader.read_exact(&mut bytes)?;
+                u64::from_be_bytes(bytes)
+            }
+            len => len as u64,
+        };
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(Error::new(ErrorKind::InvalidData, "Fragmented or oversized WebSocket control frame"));
+        }
+        if len > max_size as u64 {
+            return Err(Error::new(ErrorKind::FileTooLarge, format!("WebSocket frame of {} bytes exceeds the maximum of {}", len, max_size)));
+        }
+
+        let mut mask = [0u8; 4];
+        if masked {
+            reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        reader.read_exact(&mut payload)?;
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        Ok(Frame { fin, compressed, opcode, payload })
+    }
+}
+
+/// Status code and reason of a Close frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// A complete WebSocket message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+/// Options for the opening handshake and message handling
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    deflate: bool,
+    max_message_size: usize,
+    fragment_size: Option<usize>,
+    protocols: Vec<String>,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WebSocketConfig {
+    /// No compression, 64 MiB messages, unfragmented sends
+    pub fn new() -> Self {
+        WebSocketConfig {
+            deflate: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            fragment_size: None,
+            protocols: Vec::new(),
+        }
+    }
+
+    /// Offer permessage-deflate (RFC 7692); used only if the server accepts it
+    pub fn set_deflate(&mut self, enabled: bool) -> &mut Self {
+        self.deflate = enabled;
+        self
+    }
+
+    /// Largest message (after decompression) accepted from the server
+    pub fn set_max_message_size(&mut self, max_message_size: usize) -> &mut Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Split outgoing messages into frames of at most `fragment_size` bytes
+    pub fn set_fragment_size(&mut self, fragment_size: usize) -> &mut Self {
+        self.fragment_size = Some(fragment_size.max(1));
+        self
+    }
+
+    /// Offer a subprotocol in Sec-WebSocket-Protocol, preferred first
+    pub fn add_protocol(&mut self, protocol: &str) -> &mut Self {
+        self.protocols.push(protocol.to_string());
+        self
+    }
+}
+
+/// Negotiated permessage-deflate state
+struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+    reset_compress: bool,
+    reset_decompress: bool,
+}
+
+impl Deflate {
+    fn new(reset_compress: bool, reset_decompress: bool) -> Self {
+        Deflate {
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            reset_compress,
+            reset_decompress,
+        }
+    }
+
+    /// Apply the server's Sec-WebSocket-Extensions response
+    fn negotiate(extensions: &str, offered: bool) -> Result<Option<Deflate>> {
+        let mut deflate = None;
+        for extension in extensions.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut params = extension.split(';').map(str::trim);
+            if params.next() != Some("permessage-deflate") || !offered || deflate.is_some() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Server selected an extension that was not offered: {}", extension)));
+            }
+
+            let (mut reset_compress, mut reset_decompress) = (false, false);
+            for param in params {
+                let (name, value) = match param.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                    None => (param, None),
+                };
+                match name {
+                    "client_no_context_takeover" => reset_compress = true,
+                    "server_no_context_takeover" => reset_decompress = true,
+                    // Our 32 KiB window can inflate any smaller server window
+                    "server_max_window_bits" if value.and_then(|v| v.parse::<u8>().ok()).is_some_and(|bits| (8..=15).contains(&bits)) => {}
+                    _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported permessage-deflate parameter: {}", param))),
+                }
+            }
+            deflate = Some(Deflate::new(reset_compress, reset_decompress));
+        }
+        Ok(deflate)
+    }
+
+    fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if self.reset_compress {
+            self.compress.reset();
+        }
+
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let mut input = data;
+        loop {
+            out.reserve(input.len().max(64));
+            let before = self.compress.total_in();
+            self.compress.compress_vec(input, &mut out, FlushCompress::Sync)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            input = &input[(self.compress.total_in() - before) as usize..];
+            // The flush is complete once the output no longer fills the buffer
+            if input.is_empty() && out.len() < out.capacity() {
+                break;
+            }
+        }
+
+        if out.ends_with(&DEFLATE_TAIL) {
+            out.truncate(out.len() - DEFLATE_TAIL.len());
+        }
+        Ok(out)
+    }
+
+    fn decompress(&mut self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        if self.reset_decompress {
+            self.decompress.reset(false);
+        }
+
+        let mut input = Vec::with_capacity(data.len() + DEFLATE_TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&DEFLATE_TAIL);
+
+        let mut out = Vec::with_capacity(data.len() * 2 + 64);
+        let mut offset = 0;
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            let (before_in, before_out) = (self.decompress.total_in(), self.decompress.total_out());
+            self.decompress.decompress_vec(&input[offset..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            offset += (self.decompress.total_in() - before_in) as usize;
+
+            if out.len() > max_size {
+                return Err(Error::new(ErrorKind::FileTooLarge, "Decompressed WebSocket message exceeds the maximum size"));
+            }
+            let stalled = self.decompress.total_in() == before_in && self.decompress.total_out() == before_out;
+            if (offset == input.len() && out.len() < out.capacity()) || stalled {
+                break;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// RFC 6455 client connection over a buffered stream such as `HttpStream`
+///
+/// Pings are answered automatically (and still returned from `read`), and a
+/// Close from the server is echoed. The connection owns nothing beyond its
+/// stream, so it can be kept in a session and used across requests.
+pub struct WebSocket<S> {
+    stream: S,
+    config: WebSocketConfig,
+    deflate: Option<Deflate>,
+    protocol: Option<String>,
+    /// Data message being reassembled from fragments: opcode, compressed, payload
+    partial: Option<(Opcode, bool, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: BufRead + Write> WebSocket<S> {
+    /// Run the opening handshake over `stream`
+    ///
+    /// `request` must be a GET whose path is the request target (e.g.
+    /// "/chat"); its headers are sent along with the upgrade headers.
+    pub fn handshake(mut stream: S, request: &HttpRequest, host: &str, config: WebSocketConfig) -> Result<Self> {
+        let key = STANDARD.encode(rand::random::<[u8; 16]>());
+
+        let mut upgrade = request.clone();
+        upgrade.set_accept_encoding(false)
+            .set_header("Connection", "Upgrade")
+            .set_header("Upgrade", "websocket")
+            .set_header("Sec-WebSocket-Version", "13")
+            .set_header("Sec-WebSocket-Key", &key);
+        if config.deflate {
+            upgrade.set_header("Sec-WebSocket-Extensions", "permessage-deflate");
+        }
+        if !config.protocols.is_empty() {
+            upgrade.set_header("Sec-WebSocket-Protocol", &config.protocols.join(", "));
+        }
+        stream.write_all(upgrade.build(host).as_bytes())?;
+        stream.flush()?;
+
+        let head = http1::read_head(&mut stream, &mut ResponseDecoder::new("GET"))?;
+        let handshake_error = |reason: String| Error::new(ErrorKind::InvalidData, format!("WebSocket handshake failed: {}", reason));
+        if head.status != 101 {
+            return Err(handshake_error(format!("status {} {}", head.status, head.status_text)));
+        }
+        if !head.header("upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"))
+            || !head.header("connection").is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("upgrade")))
+        {
+            return Err(handshake_error("missing Upgrade: websocket".to_string()));
+        }
+        let expected = STANDARD.encode(Sha1::digest(format!("{}{}", key, ACCEPT_GUID)));
+        if head.header("sec-websocket-accept") != Some(expected.as_str()) {
+            return Err(handshake_error("invalid Sec-WebSocket-Accept".to_string()));
+        }
+
+        let deflate = match head.header("sec-websocket-extensions") {
+            Some(extensions) => Deflate::negotiate(extensions, config.deflate)?,
+            None => None,
+        };
+        let protocol = head.header("sec-websocket-protocol").map(str::to_string);
+        if protocol.as_ref().is_some_and(|p| !config.protocols.contains(p)) {
+            return Err(handshake_error("server selected a subprotocol that was not offered".to_string()));
+        }
+
+        Ok(WebSocket {
+            stream,
+            config,
+            deflate,
+            protocol,
+            partial: None,
+            close_sent: false,
+            close_received: false,
+        })
+    }
+
+    /// Subprotocol selected by the server, if any
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Whether permessage-deflate was negotiated
+    pub fn is_compressed(&self) -> bool {
+        self.deflate.is_some()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<()> {
+        self.send(Message::Text(text.to_string()))
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<()> {
+        self.send(Message::Binary(data.to_vec()))
+    }
+
+    pub fn ping(&mut self, payload: &[u8]) -> Result<()> {
+        self.send(Message::Ping(payload.to_vec()))
+    }
+
+    /// Start the close handshake; keep calling `read` until it returns the server's Close
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<()> {
+        self.send(Message::Close(Some(CloseFrame { code, reason: reason.to_string() })))
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        if self.close_sent {
+            return Err(Error::new(ErrorKind::NotConnected, "WebSocket is closing"));
+        }
+        match message {
+            Message::Text(text) => self.send_data(Opcode::Text, text.as_bytes()),
+            Message::Binary(data) => self.send_data(Opcode::Binary, &data),
+            Message::Ping(payload) => self.send_control(Opcode::Ping, payload),
+            Message::Pong(payload) => self.send_control(Opcode::Pong, payload),
+            Message::Close(frame) => {
+                let payload = frame.map(|frame| {
+                    let mut payload = frame.code.to_be_bytes().to_vec();
+                    payload.extend_from_slice(frame.reason.as_bytes());
+                    payload
+                }).unwrap_or_default();
+                self.send_control(Opcode::Close, payload)?;
+                self.close_sent = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Read the next message, reassembling fragments and inflating compressed ones
+    ///
+    /// After the server's Close has been returned, further reads fail with
+    /// `NotConnected`. A protocol violation sends a Close and fails.
+    pub fn read(&mut self) -> Result<Message> {
+        if self.close_received {
+            return Err(Error::new(ErrorKind::NotConnected, "WebSocket is closed"));
+        }
+
+        loop {
+            let frame = match Frame::read(&mut self.stream, self.config.max_message_size, false) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == ErrorKind::InvalidData => return Err(self.fail(CLOSE_PROTOCOL_ERROR, e)),
+                Err(e) if e.kind() == ErrorKind::FileTooLarge => return Err(self.fail(CLOSE_TOO_BIG, e)),
+                Err(e) => return Err(e),
+            };
+            if frame.compressed && (self.deflate.is_none() || frame.opcode.is_control() || frame.opcode == Opcode::Continuation) {
+                return Err(self.fail(CLOSE_PROTOCOL_ERROR, Error::new(ErrorKind::InvalidData, "Unexpected RSV1 bit in WebSocket frame")));
+            }
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    if !self.close_sent {
+                        self.send_control(Opcode::Pong, frame.payload.clone())?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                Opcode::Pong => return Ok(Message::Pong(frame.payload)),
+                Opcode::Close => return self.receive_close(&frame.payload),
+                Opcode::Text | Opcode::Binary => {
+                    if self.partial.is_some() {
+                        return Err(self.fail(CLOSE_PROTOCOL_ERROR, Error::new(ErrorKind::InvalidData, "New WebSocket message before the previous one finished")));
+                    }
+                    self.partial = Some((frame.opcode, frame.compressed, frame.payload));
+                }
+                Opcode::Continuation => match &mut self.partial {
+                    Some((_, _, payload)) => payload.extend_from_slice(&frame.payload),
+                    None => return Err(self.fail(CLOSE_PROTOCOL_ERROR, Error::new(ErrorKind::InvalidData, "WebSocket continuation without a message"))),
+                },
+            }
+
+            if self.partial.as_ref().is_some_and(|(_, _, payload)| payload.len() > self.config.max_message_size) {
+                return Err(self.fail(CLOSE_TOO_BIG, Error::new(ErrorKind::FileTooLarge, "WebSocket message exceeds the maximum size")));
+            }
+            if frame.fin
+                && let Some((opcode, compressed, payload)) = self.partial.take()
+            {
+                return self.finish_message(opcode, compressed, payload);
+            }
+        }
+    }
+
+    fn finish_message(&mut self, opcode: Opcode, compressed: bool, payload: Vec<u8>) -> Result<Message> {
+        let payload = match (&mut self.deflate, compressed) {
+            (Some(deflate), true) => match deflate.decompress(&payload, self.config.max_message_size) {
+                Ok(payload) => payload,
+                Err(e) if e.kind() == ErrorKind::FileTooLarge => return Err(self.fail(CLOSE_TOO_BIG, e)),
+                Err(e) => return Err(self.fail(CLOSE_INVALID_DATA, e)),
+            },
+            _ => payload,
+        };
+
+        if opcode == Opcode::Binary {
+            return Ok(Message::Binary(payload));
+        }
+        match String::from_utf8(payload) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(CLOSE_INVALID_DATA, Error::new(ErrorKind::InvalidData, "WebSocket text message is not valid UTF-8"))),
+        }
+    }
+
+    fn receive_close(&mut self, payload: &[u8]) -> Result<Message> {
+        let frame = match payload.len() {
+            0 => None,
+            1 => return Err(self.fail(CLOSE_PROTOCOL_ERROR, Error::new(ErrorKind::InvalidData, "Truncated WebSocket close code"))),
+            _ => {
+                let reason = String::from_utf8(payload[2..].to_vec())
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "WebSocket close reason is not valid UTF-8"))?;
+                Some(CloseFrame { code: u16::from_be_bytes([payload[0], payload[1]]), reason })
+            }
+        };
+
+        self.close_received = true;
+        if !self.close_sent {
+            // Echo the status code to complete the close handshake
+            let echo = frame.as_ref().map(|frame| frame.code.to_be_bytes().to_vec()).unwrap_or_default();
+            self.send_control(Opcode::Close, echo)?;
+            self.close_sent = true;
+        }
+        Ok(Message::Close(frame))
+    }
+
+    /// Send a Close with `code` (best effort) and return `error`
+    fn fail(&mut self, code: u16, error: Error) -> Error {
+        if !self.close_sent {
+            let _ = self.send_control(Opcode::Close, code.to_be_bytes().to_vec());
+            self.close_sent = true;
+        }
+        self.close_received = true;
+        error
+    }
+
+    fn send_data(&mut self, opcode: Opcode, data: &[u8]) -> Result<()> {
+        let (payload, compressed) = match &mut self.deflate {
+            Some(deflate) => (deflate.compress(data)?, true),
+            None => (data.to_vec(), false),
+        };
+
+        let fragment_size = self.config.fragment_size.unwrap_or(usize::MAX);
+        let mut fragments: Vec<&[u8]> = payload.chunks(fragment_size).collect();
+        if fragments.is_empty() {
+            fragments.push(&[]);
+        }
+
+        let last = fragments.len() - 1;
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            let frame = Frame {
+                fin: i == last,
+                compressed: compressed && i == 0,
+                opcode: if i == 0 { opcode } else { Opcode::Continuation },
+                payload: fragment.to_vec(),
+            };
+            self.stream.write_all(&frame.encode(Some(rand::random())))?;
+        }
+        self.stream.flush()
+    }
+
+    fn send_control(&mut self, opcode: Opcode, payload: Vec<u8>) -> Result<()> {
+        if payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(Error::new(ErrorKind::InvalidInput, "WebSocket control payload exceeds 125 bytes"));
+        }
+        let frame = Frame { fin: true, compressed: false, opcode, payload };
+        self.stream.write_all(&frame.encode(Some(rand::random())))?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::HttpStream;
+    use crate::test_support::{listen, read_head};
+    use std::io::BufReader;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_frame_lengths_round_trip() {
+        for len in [0, 125, 126, 65535, 65536] {
+            let frame = Frame { fin: true, compressed: false, opcode: Opcode::Binary, payload: vec![7; len] };
+            let encoded = frame.encode(Some([1, 2, 3, 4]));
+            let decoded = Frame::read(&mut &encoded[..], usize::MAX, true).unwrap();
+            assert_eq!(decoded, frame);
+            assert!(Frame::read(&mut &encoded[..], usize::MAX, false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_fragmented_deflate_session() {
+        let (listener, port) = listen();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let head = read_head(&mut reader);
+            let key = head.lines()
+                .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+                .unwrap();
+            let accept = STANDARD.encode(Sha1::digest(format!("{}{}", key, ACCEPT_GUID)));
+            let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\nSec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover\r\n\r\n", accept);
+            let mut writer = reader.get_ref().try_clone().unwrap();
+            writer.write_all(response.as_bytes()).unwrap();
+
+            // A fragmented text message with a ping between the fragments
+            let frames = [
+                Frame { fin: false, compressed: false, opcode: Opcode::Text, payload: b"Hel".to_vec() },
+                Frame { fin: true, compressed: false, opcode: Opcode::Ping, payload: b"hb".to_vec() },
+                Frame { fin: true, compressed: false, opcode: Opcode::Continuation, payload: b"lo".to_vec() },
+            ];
+            for frame in &frames {
+                writer.write_all(&frame.encode(None)).unwrap();
+            }
+
+            let pong = Frame::read(&mut reader, usize::MAX, true).unwrap();
+            assert_eq!((pong.opcode, pong.payload.as_slice()), (Opcode::Pong, &b"hb"[..]));
+
+            // Reassemble the client's compressed, fragmented message and echo it back
+            let mut deflate = Deflate::new(true, false);
+            let first = Frame::read(&mut reader, usize::MAX, true).unwrap();
+            assert!(first.compressed && !first.fin);
+            let mut payload = first.payload;
+            loop {
+                let frame = Frame::read(&mut reader, usize::MAX, true).unwrap();
+                payload.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    break;
+                }
+            }
+            let text = deflate.decompress(&payload, usize::MAX).unwrap();
+            let echo = Frame { fin: true, compressed: true, opcode: Opcode::Text, payload: deflate.compress(&text).unwrap() };
+            writer.write_all(&echo.encode(None)).unwrap();
+
+            let close = Frame::read(&mut reader, usize::MAX, true).unwrap();
+            assert_eq!(close.opcode, Opcode::Close);
+            writer.write_all(&Frame { fin: true, compressed: false, opcode: Opcode::Close, payload: close.payload[..2].to_vec() }.encode(None)).unwrap();
+            head
+        });
+
+        let stream = HttpStream::Plain(BufReader::new(TcpStream::connect(("127.0.0.1", port)).unwrap()));
+        let mut config = WebSocketConfig::new();
+        config.set_deflate(true).set_fragment_size(4);
+        let mut socket = WebSocket::handshake(stream, &HttpRequest::new("GET", "/chat"), "127.0.0.1", config).unwrap();
+        assert!(socket.is_compressed());
+
+        assert_eq!(socket.read().unwrap(), Message::Ping(b"hb".to_vec()));
+        assert_eq!(socket.read().unwrap(), Message::Text("Hello".to_string()));
+
+        let text = "compressible compressible compressible";
+        socket.send_text(text).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Text(text.to_string()));
+
+        socket.close(1000, "bye").unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Close(Some(CloseFrame { code: 1000, reason: String::new() })));
+        assert_eq!(socket.read().unwrap_err().kind(), ErrorKind::NotConnected);
+
+        let head = server.join().unwrap();
+        assert!(head.starts_with("GET /chat HTTP/1.1\r\n"));
+        assert!(head.contains("Connection: Upgrade\r\n"));
+        assert!(!head.contains("keep-alive"));
+    }
+}